@@ -1,9 +1,10 @@
-use crate::{riot, tia, AddressBus};
+use crate::{state::{fnv1a, StateError, StateReader, StateWriter}, riot, tia::{self, InputPort, Pot}, controller::{Controller, ControllerEvent, Joystick, Port}, AddressBus, TvStandard};
 
 pub struct Atari2600 {
     pub rom: Vec<u8>,
     pub riot: riot::Riot,
     pub tia: tia::Tia,
+    ports: [Box<dyn Controller>; 2],
     bank_offset: usize
 }
 
@@ -29,10 +30,89 @@ impl Atari2600 {
             rom,
             riot: riot::Riot::new(),
             tia: tia::Tia::new(),
+            ports: [Box::new(Joystick::new()), Box::new(Joystick::new())],
             bank_offset: 0
         }
     }
 
+    pub fn connect(&mut self, port: Port, controller: Box<dyn Controller>) {
+        self.ports[port as usize] = controller;
+        self.sync_ports();
+    }
+
+    pub fn controller_event(&mut self, port: Port, event: ControllerEvent) {
+        self.ports[port as usize].event(event);
+        self.sync_ports();
+    }
+
+    // the left port drives the high nibble of SWCHA, INPT0/INPT1 and INPT4, the right port the rest
+    fn sync_ports(&mut self) {
+        let mut porta = 0;
+        let beam = self.tia.beam();
+
+        for (index, controller) in self.ports.iter_mut().enumerate() {
+            let shift = if index == Port::Left as usize { 4 } else { 0 };
+            let input = if index == Port::Left as usize { InputPort::Input4 } else { InputPort::Input5 };
+            let pots = if index == Port::Left as usize { [Pot::Pot0, Pot::Pot1] } else { [Pot::Pot2, Pot::Pot3] };
+            let pins = controller.pins((self.riot.porta_output() >> shift) & 0x0F, beam);
+
+            porta |= (pins.data & 0x0F) << shift;
+            self.tia.set_input(input, pins.fire);
+            self.tia.set_light_target(input, controller.light_target());
+
+            for (pot, charge) in pots.into_iter().zip(pins.pots) {
+                match charge {
+                    Some(position) => self.tia.set_paddle(pot, position),
+                    None => self.tia.disconnect_paddle(pot)
+                }
+            }
+        }
+
+        self.riot.porta_input(porta);
+    }
+
+    pub fn set_tv_standard(&mut self, standard: Option<TvStandard>) {
+        self.tia.set_tv_standard(standard);
+    }
+
+    pub fn tv_standard(&self) -> TvStandard {
+        self.tia.tv_standard()
+    }
+
+    // the ROM itself isn't saved, only enough to check a state is loaded back into the same one
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&fnv1a(&self.rom));
+        writer.put(&(self.bank_offset as u32));
+        self.riot.save_state(writer);
+        self.tia.save_state(writer);
+
+        for controller in &self.ports {
+            writer.put(&controller.save_state());
+        }
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        if reader.get::<u64>()? != fnv1a(&self.rom) {
+            return Err(StateError::RomMismatch);
+        }
+
+        self.bank_offset = reader.get::<u32>()? as usize;
+
+        if !self.bank_offset.is_multiple_of(4096) || (self.bank_offset != 0 && self.bank_offset + 4096 > self.rom.len()) {
+            return Err(StateError::Invalid);
+        }
+
+        self.riot.load_state(reader)?;
+        self.tia.load_state(reader)?;
+
+        for controller in self.ports.iter_mut() {
+            controller.load_state(&reader.get::<Vec<u8>>()?)?;
+        }
+
+        self.sync_ports();
+        Ok(())
+    }
+
     fn decode(addr: u16) -> Atari2600Chip {
         if addr & addresses::CART_MASK == addresses::CART_SELECT {
             Atari2600Chip::Cartridge
@@ -62,8 +142,14 @@ impl AddressBus for Atari2600 {
                 }
                 _ => self.rom[addr as usize & 0xFFF + self.bank_offset]
             }
-            Atari2600Chip::RIOT => self.riot.read(addr),
-            Atari2600Chip::TIA => self.tia.read(addr)
+            Atari2600Chip::RIOT => {
+                self.sync_ports();
+                self.riot.read(addr)
+            }
+            Atari2600Chip::TIA => {
+                self.sync_ports();
+                self.tia.read(addr)
+            }
         }
     }
 
@@ -80,7 +166,10 @@ impl AddressBus for Atari2600 {
                 }
                 _ => unimplemented!("write to ROM address: 0x{:04X}", addr)
             }
-            Atari2600Chip::RIOT => self.riot.write(addr, value),
+            Atari2600Chip::RIOT => {
+                self.riot.write(addr, value);
+                self.sync_ports();
+            }
             Atari2600Chip::TIA => self.tia.write(addr, value)
         }
     }
@@ -1,17 +1,240 @@
-use std::{thread, time::{Duration, Instant}};
+use std::{fs::File, io::BufWriter, thread, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
 
-use atari2600::{atari2600::{Atari2600}, tia, riot::{Player, JoystickDirection}, AudioConverter};
-use sdl2::{event::Event, pixels::{Color, PixelFormatEnum}, keyboard::Keycode, audio::AudioSpecDesired, render::TextureAccess};
+use atari2600::{tia, riot::ConsoleSwitches, savekey::{AtariVox, SaveKey}, controller::{Controller, ControllerEvent, DrivingController, Genesis, Joystick, Keypad, LightGun, Paddles, PointingDevice, PointingDeviceKind, Port}, Capture, FrameView, Movie, MovieStart, Playback, Player, PowerOnState, Recorder, Rewind, System, TvStandard};
+use sdl2::{event::Event, mouse::MouseButton, pixels::{Color, PixelFormatEnum}, keyboard::Keycode, audio::AudioSpecDesired, render::TextureAccess, rect::Rect};
+
+// about three minutes at 60Hz
+const REWIND_CAPACITY: usize = 60 * 60 * 3;
+
+fn controller(name: &str) -> Box<dyn Controller> {
+    match name {
+        "joystick" => Box::new(Joystick::new()),
+        "paddles" => Box::new(Paddles::new()),
+        "keypad" => Box::new(Keypad::new()),
+        "driving" => Box::new(DrivingController::new()),
+        "genesis" => Box::new(Genesis::new()),
+        "trakball" => Box::new(PointingDevice::new(PointingDeviceKind::TrakBall)),
+        "amigamouse" => Box::new(PointingDevice::new(PointingDeviceKind::AmigaMouse)),
+        "atarimouse" => Box::new(PointingDevice::new(PointingDeviceKind::AtariMouse)),
+        "lightgun" => Box::new(LightGun::new()),
+        "savekey" => Box::new(SaveKey::new("savekey_eeprom.dat")),
+        "atarivox" => Box::new(AtariVox::new("savekey_eeprom.dat", "atarivox_speech.log")),
+        _ => panic!("unknown controller: {}", name)
+    }
+}
+
+// movies start from an erased EEPROM and never touch the files, so they play back the same anywhere
+fn movie_controller(name: &str) -> Box<dyn Controller> {
+    match name {
+        "savekey" => Box::new(SaveKey::blank()),
+        "atarivox" => Box::new(AtariVox::blank()),
+        _ => controller(name)
+    }
+}
+
+fn controller_key(keycode: Keycode, pressed: bool) -> Option<(Port, ControllerEvent)> {
+    let keypad_keys = [
+        (Keycode::Num1, Keycode::Kp1), (Keycode::Num2, Keycode::Kp2), (Keycode::Num3, Keycode::Kp3),
+        (Keycode::Num4, Keycode::Kp4), (Keycode::Num5, Keycode::Kp5), (Keycode::Num6, Keycode::Kp6),
+        (Keycode::Num7, Keycode::Kp7), (Keycode::Num8, Keycode::Kp8), (Keycode::Num9, Keycode::Kp9),
+        (Keycode::Minus, Keycode::KpMultiply), (Keycode::Num0, Keycode::Kp0), (Keycode::Equals, Keycode::KpEnter)
+    ];
+
+    for (index, (left, right)) in keypad_keys.iter().enumerate() {
+        if keycode == *left {
+            return Some((Port::Left, ControllerEvent::Key(index, pressed)));
+        }
+
+        if keycode == *right {
+            return Some((Port::Right, ControllerEvent::Key(index, pressed)));
+        }
+    }
+
+    match keycode {
+        Keycode::Up => Some((Port::Left, ControllerEvent::Up(pressed))),
+        Keycode::Down => Some((Port::Left, ControllerEvent::Down(pressed))),
+        Keycode::Left => Some((Port::Left, ControllerEvent::Left(pressed))),
+        Keycode::Right => Some((Port::Left, ControllerEvent::Right(pressed))),
+        Keycode::Space => Some((Port::Left, ControllerEvent::Fire(pressed))),
+        Keycode::LShift => Some((Port::Left, ControllerEvent::Button(0, pressed))),
+        Keycode::I => Some((Port::Right, ControllerEvent::Up(pressed))),
+        Keycode::K => Some((Port::Right, ControllerEvent::Down(pressed))),
+        Keycode::J => Some((Port::Right, ControllerEvent::Left(pressed))),
+        Keycode::L => Some((Port::Right, ControllerEvent::Right(pressed))),
+        Keycode::U => Some((Port::Right, ControllerEvent::Fire(pressed))),
+        Keycode::O => Some((Port::Right, ControllerEvent::Button(0, pressed))),
+        _ => None
+    }
+}
+
+// select and reset are held like the console buttons, the other switches toggle on each press
+fn switch_key(switches: &mut ConsoleSwitches, keycode: Keycode, pressed: bool, repeat: bool) -> bool {
+    let toggle = pressed && !repeat;
+
+    match keycode {
+        Keycode::S => switches.select = pressed,
+        Keycode::R => switches.reset = pressed,
+        Keycode::C if toggle => switches.color = !switches.color,
+        Keycode::P if toggle => switches.pause = !switches.pause,
+        Keycode::F1 if toggle => switches.difficulty[0] = switches.difficulty[0].toggled(),
+        Keycode::F2 if toggle => switches.difficulty[1] = switches.difficulty[1].toggled(),
+        Keycode::C | Keycode::P | Keycode::F1 | Keycode::F2 => (),
+        _ => return false
+    }
+
+    true
+}
+
+fn save_movie(recorder: Recorder, path: &str) {
+    let movie = recorder.finish();
+
+    match std::fs::write(path, movie.to_bytes()) {
+        Ok(()) => println!("saved {} frame movie to {}", movie.frames.len(), path),
+        Err(e) => println!("could not save movie to {}: {}", path, e)
+    }
+}
+
+fn save_screenshot(system: &System, view: FrameView, path: &str) {
+    match std::fs::write(path, system.screenshot_png(view)) {
+        Ok(()) => println!("saved screenshot to {}", path),
+        Err(e) => println!("could not save screenshot to {}: {}", path, e)
+    }
+}
 
 fn main() {
     let args = std::env::args().collect::<Vec<String>>();
     let rom = std::fs::read(&args[1]).unwrap();
 
-    let atari = Atari2600::new(rom);
+    let mut tv_standard = None;
+    let mut power_on = PowerOnState::Fixed;
+    let mut controller_names = [String::from("joystick"), String::from("joystick")];
+    let mut record_path = None;
+    let mut play_path = None;
+    let mut headless_frames = None;
+    let mut screenshot = false;
+    let mut capture_path = None;
+    let mut screenshot_view = FrameView::default();
+
+    for option in args[2..].chunks(2) {
+        match option {
+            [name, value] => match name.as_str() {
+                "--tv" => tv_standard = match value.as_str() {
+                    "ntsc" => Some(TvStandard::Ntsc),
+                    "pal" => Some(TvStandard::Pal),
+                    "pal60" => Some(TvStandard::Pal60),
+                    "secam" => Some(TvStandard::Secam),
+                    "auto" => None,
+                    _ => panic!("unknown tv standard: {}", value)
+                },
+                "--power-on" => power_on = match value.as_str() {
+                    "fixed" => PowerOnState::Fixed,
+                    "random" => PowerOnState::Random(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64),
+                    seed => PowerOnState::Random(seed.parse().expect("power-on seed must be a number"))
+                },
+                "--left" => controller_names[Port::Left as usize] = value.clone(),
+                "--right" => controller_names[Port::Right as usize] = value.clone(),
+                "--record" => record_path = Some(value.clone()),
+                "--play" => play_path = Some(value.clone()),
+                "--headless" => headless_frames = Some(value.parse::<u32>().expect("headless frame count must be a number")),
+                "--screenshot-after" => {
+                    headless_frames = Some(value.parse::<u32>().expect("screenshot frame count must be a number"));
+                    screenshot = true;
+                }
+                "--capture" => capture_path = Some(value.clone()),
+                "--screenshot-width" => screenshot_view.hblank = match value.as_str() {
+                    "160" => false,
+                    "228" => true,
+                    _ => panic!("screenshot width must be 160 or 228")
+                },
+                "--screenshot-aspect" => screenshot_view.double_width = match value.as_str() {
+                    "1" => false,
+                    "2" => true,
+                    _ => panic!("screenshot aspect must be 1 or 2")
+                },
+                _ => panic!("unknown option: {}", name)
+            },
+            _ => panic!("missing value for option: {}", option[0])
+        }
+    }
+
+    let mut player = None;
+    let mut recording = None;
+
+    // a movie brings its own start and controllers
+    let mut system = match &play_path {
+        Some(path) => {
+            let movie = std::fs::read(path).map_err(|e| e.to_string())
+                .and_then(|data| Movie::from_bytes(&data).map_err(|e| e.to_string()))
+                .unwrap_or_else(|e| panic!("could not load movie {}: {}", path, e));
+
+            let system = movie.start(rom, movie_controller).unwrap_or_else(|e| panic!("could not start movie {}: {}", path, e));
+            controller_names = movie.controllers.clone();
+            println!("playing movie {} ({} frames)", path, movie.frames.len());
+            player = Some(Player::new(movie));
+            system
+        }
+        None => {
+            if let PowerOnState::Random(seed) = power_on {
+                println!("power-on seed: {}", seed);
+            }
+
+            let connect = if record_path.is_some() { movie_controller } else { controller };
+            let mut system = System::with_power_on(rom, power_on);
+            system.connect(Port::Left, connect(&controller_names[Port::Left as usize]));
+            system.connect(Port::Right, connect(&controller_names[Port::Right as usize]));
+            system
+        }
+    };
+
+    system.set_tv_standard(tv_standard);
 
-    let mut cpu = atari2600::MOS6502::new(atari);
-    cpu.reset();
-    cpu.get_bus().riot.switch_color(true);
+    if let Some(path) = record_path {
+        println!("recording movie to {}", path);
+        recording = Some((Recorder::new(&system, MovieStart::PowerOn(power_on), controller_names.clone()), path));
+    }
+
+    let mut capture = capture_path.map(|path| {
+        let create = |extension| BufWriter::new(File::create(format!("{}.{}", path, extension))
+            .unwrap_or_else(|e| panic!("could not create {}.{}: {}", path, extension, e)));
+
+        println!("capturing to {0}.y4m and {0}.wav", path);
+        Capture::new(&mut system, screenshot_view, create("y4m"), create("wav"))
+    });
+
+    // run without a window at full speed, then quit
+    if let Some(frames) = headless_frames {
+        for _ in 0..frames {
+            match player.as_mut().map(|playing| playing.run_frame(&mut system)) {
+                Some(Playback::Diverged(frame)) => println!("movie diverged from the recording at frame {}", frame),
+                Some(Playback::Playing) => (),
+                Some(Playback::Finished) | None => {
+                    system.run_frame();
+                }
+            }
+
+            if let Some((recorder, _)) = &mut recording {
+                recorder.frame(&system);
+            }
+
+            if let Some(capturing) = &mut capture {
+                capturing.frame(&mut system).expect("could not write capture");
+            }
+        }
+
+        if screenshot {
+            save_screenshot(&system, screenshot_view, &format!("{}.png", args[1]));
+        }
+
+        if let Some((recorder, path)) = recording {
+            save_movie(recorder, &path);
+        }
+
+        if let Some(capturing) = capture {
+            capturing.finish(&mut system).expect("could not finish capture");
+        }
+
+        return;
+    }
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -21,8 +244,9 @@ fn main() {
     let spec = AudioSpecDesired{ freq: Some(44100), channels: Some(2), samples: Some(512) };
     let audio_device = audio_subsystem.open_queue::<u8, _>(None, &spec).unwrap();
     audio_device.resume();
+    system.set_audio_output(true);
 
-    let window = video_subsystem.window("atari2600", (tia::CLOCKS_PER_SCANLINE * SCALE) as u32, (tia::NUM_SCANLINES * SCALE) as u32)
+    let window = video_subsystem.window("atari2600", (tia::CLOCKS_PER_SCANLINE * SCALE) as u32, (system.visible_window().height * SCALE) as u32)
         .position_centered()
         .build()
         .expect("could not initialize video subsystem");
@@ -35,106 +259,186 @@ fn main() {
     canvas.present();
 
     let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator.create_texture(PixelFormatEnum::RGB24, TextureAccess::Streaming, tia::CLOCKS_PER_SCANLINE as u32, tia::NUM_SCANLINES as u32).unwrap();
-    let mut pixels = [0u8; tia::NUM_SCANLINES as usize * tia::CLOCKS_PER_SCANLINE as usize * 3];
+    let mut texture = texture_creator.create_texture(PixelFormatEnum::RGB24, TextureAccess::Streaming, tia::CLOCKS_PER_SCANLINE as u32, tia::MAX_SCANLINES as u32).unwrap();
+    let mut pixels = [0u8; tia::MAX_SCANLINES as usize * tia::CLOCKS_PER_SCANLINE as usize * 3];
 
     let mut event_pump = sdl_context.event_pump().unwrap();
-    let player = Player::Zero;
 
-    let mut audio_converter = AudioConverter::new(85);
+    let mut state_slot = 0;
+    let mut screenshot_num = 0;
+    let mut rewind = Rewind::new(REWIND_CAPACITY);
+    let mut rewinding = false;
     let mut frame_num = 0;
     let start_time = Instant::now();
     
     'main_loop: loop {
+        let mut inputs = Vec::new();
+
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit {..} => break 'main_loop,
-                Event::KeyDown { timestamp: _, window_id: _, keycode, scancode: _, keymod: _, repeat: _ } => match keycode {
-                    Some(keycode) => match keycode {
-                        Keycode::Right => cpu.get_bus().riot.switch_joystick(player, JoystickDirection::Right, true),
-                        Keycode::Left => cpu.get_bus().riot.switch_joystick(player, JoystickDirection::Left, true),
-                        Keycode::Down => cpu.get_bus().riot.switch_joystick(player, JoystickDirection::Down, true),
-                        Keycode::Up => cpu.get_bus().riot.switch_joystick(player, JoystickDirection::Up, true),
-                        Keycode::Space => cpu.get_bus().tia.input4(0x00),
-                        Keycode::S => cpu.get_bus().riot.switch_select(false),
-                        Keycode::R => cpu.get_bus().riot.switch_reset(false),
-                        _ => ()
-                    },
-                    None => (),
+                Event::KeyDown { keycode: Some(Keycode::F12), repeat: false, .. } => {
+                    save_screenshot(&system, screenshot_view, &format!("{}.screenshot{}.png", args[1], screenshot_num));
+                    screenshot_num += 1;
                 }
-                Event::KeyUp { timestamp: _, window_id: _, keycode, scancode: _, keymod: _, repeat: _ } => match keycode {
-                    Some(keycode) => match keycode {
-                        Keycode::Right => cpu.get_bus().riot.switch_joystick(player, JoystickDirection::Right, false),
-                        Keycode::Left => cpu.get_bus().riot.switch_joystick(player, JoystickDirection::Left, false),
-                        Keycode::Down => cpu.get_bus().riot.switch_joystick(player, JoystickDirection::Down, false),
-                        Keycode::Up => cpu.get_bus().riot.switch_joystick(player, JoystickDirection::Up, false),
-                        Keycode::Space => cpu.get_bus().tia.input4(0x80),
-                        Keycode::S => cpu.get_bus().riot.switch_select(true),
-                        Keycode::R => cpu.get_bus().riot.switch_reset(true),
-                        _ => ()
-                    },
-                    None => (),
+                _ if player.is_some() => (),
+                Event::KeyDown { keycode: Some(keycode), repeat, .. } | Event::KeyUp { keycode: Some(keycode), repeat, .. } => {
+                    let pressed = matches!(event, Event::KeyDown { .. });
+                    let mut switches = system.switches();
+
+                    let state_path = format!("{}.state{}", args[1], state_slot);
+
+                    if keycode == Keycode::Backspace {
+                        rewinding = pressed && recording.is_none();
+                    } else if pressed && !repeat && keycode == Keycode::F9 {
+                        match recording.take() {
+                            Some((recorder, path)) => save_movie(recorder, &path),
+                            None => {
+                                let path = format!("{}.movie", args[1]);
+                                println!("recording movie to {}", path);
+                                recording = Some((Recorder::new(&system, MovieStart::State(system.save_state()), controller_names.clone()), path));
+                            }
+                        }
+                    } else if pressed && !repeat && keycode == Keycode::F7 && recording.is_some() {
+                        println!("can't load a state while recording a movie");
+                    } else if pressed && !repeat && keycode == Keycode::F5 {
+                        match std::fs::write(&state_path, system.save_state()) {
+                            Ok(()) => println!("saved state to {}", state_path),
+                            Err(e) => println!("could not save state to {}: {}", state_path, e)
+                        }
+                    } else if pressed && !repeat && keycode == Keycode::F6 {
+                        state_slot = (state_slot + 1) % 10;
+                        println!("state slot {}", state_slot);
+                    } else if pressed && !repeat && keycode == Keycode::F7 {
+                        match std::fs::read(&state_path).map_err(|e| e.to_string()).and_then(|data| system.load_state(&data).map_err(|e| e.to_string())) {
+                            Ok(()) => {
+                                rewind.clear();
+                                println!("loaded state from {}", state_path);
+                            }
+                            Err(e) => println!("could not load state from {}: {}", state_path, e)
+                        }
+                    } else if switch_key(&mut switches, keycode, pressed, repeat) {
+                        system.set_switches(switches);
+                    } else if let Some((port, event)) = controller_key(keycode, pressed) {
+                        inputs.push((port, event));
+                    }
+                }
+                Event::MouseMotion { x, y, xrel, yrel, .. } => {
+                    let (width, height) = canvas.window().size();
+                    let (x, y) = (x.clamp(0, width as i32) as u32, y.clamp(0, height as i32) as u32);
+                    let position = (x * 255 / width) as u8;
+                    inputs.push((Port::Left, ControllerEvent::Paddle(0, position)));
+                    inputs.push((Port::Left, ControllerEvent::Motion(xrel, yrel)));
+
+                    let window = system.visible_window();
+                    let aim_x = (x * tia::CLOCKS_PER_SCANLINE as u32 / width) as u16;
+                    let aim_y = window.ystart + (y * window.height as u32 / height) as u16;
+                    inputs.push((Port::Left, ControllerEvent::Aim(aim_x, aim_y)));
+                }
+                Event::MouseButtonDown { mouse_btn: MouseButton::Left, .. } => {
+                    inputs.push((Port::Left, ControllerEvent::Button(0, true)));
+                    inputs.push((Port::Left, ControllerEvent::Fire(true)));
+                }
+                Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                    inputs.push((Port::Left, ControllerEvent::Button(0, false)));
+                    inputs.push((Port::Left, ControllerEvent::Fire(false)));
                 }
                 _ => ()
             }
         }
 
-        for _ in 0..1000 {
-            let wsync = {
-                let atari = cpu.get_bus();
-                atari.tia.wsync()
-            };
+        for (port, event) in inputs {
+            system.controller_event(port, event);
 
-            {
-                let atari = cpu.get_bus();
+            if let Some((recorder, _)) = &mut recording {
+                recorder.event(port, event);
+            }
+        }
 
-                for _ in 0..3 {
-                    atari.tia.cycle();
-                }
+        if rewinding {
+            if let Err(e) = rewind.step_back(&mut system) {
+                println!("could not rewind: {}", e);
+                rewind.clear();
+            }
+            thread::sleep(Duration::from_secs_f32(1.0 / system.tv_standard().frame_rate()));
+        } else if let Some(playing) = &mut player {
+            match playing.run_frame(&mut system) {
+                Playback::Playing => (),
+                Playback::Diverged(frame) => println!("movie diverged from the recording at frame {}", frame),
+                Playback::Finished => {
+                    match playing.divergence() {
+                        Some(frame) => println!("movie finished after {} frames, first divergence at frame {}", playing.frame(), frame),
+                        None => println!("movie finished after {} frames in sync", playing.frame())
+                    }
 
-                atari.riot.cycle();
+                    player = None;
+                }
+            }
+        } else {
+            system.run_frame();
+            rewind.capture(&system);
 
-                if atari.tia.draw() {
-                    atari.tia.drew();
+            if let Some((recorder, _)) = &mut recording {
+                recorder.frame(&system);
+            }
+        }
 
-                    let t = Instant::now() - start_time;
-                    
-                    if frame_num % 10 == 0 {
-                        println!("fps: {}", frame_num as f32 / t.as_secs_f32());
-                    }
+        if let (Some(capturing), false) = (&mut capture, rewinding) {
+            capturing.frame(&mut system).expect("could not write capture");
+        }
 
-                    for x in 0..tia::CLOCKS_PER_SCANLINE as usize {
-                        for y in 0..tia::NUM_SCANLINES as usize {
-                            let index = y * tia::CLOCKS_PER_SCANLINE as usize + x;
-                            let rgb = atari2600::palette_rgb(atari.tia.frame[index]);
+        let t = Instant::now() - start_time;
+        
+        if frame_num % 10 == 0 {
+            println!("fps: {}", frame_num as f32 / t.as_secs_f32());
+        }
 
-                            pixels[index * 3 + 0] = rgb.0;
-                            pixels[index * 3 + 1] = rgb.1;
-                            pixels[index * 3 + 2] = rgb.2;
+        let tv_standard = system.tv_standard();
+        let window = system.visible_window();
+        let frame = system.frame();
 
-                        }
-                    }
+        // the window follows the visible lines when the ROM's standard or kernel changes
+        let size = ((tia::CLOCKS_PER_SCANLINE * SCALE) as u32, (window.height * SCALE) as u32);
+        if canvas.window().size() != size {
+            canvas.window_mut().set_size(size.0, size.1).expect("could not resize the window");
+        }
 
-                    texture.update(None, &pixels, 3 * tia::CLOCKS_PER_SCANLINE as usize).unwrap();
-                    canvas.copy(&texture, None, None).unwrap();
-                    canvas.present();
+        for x in 0..tia::CLOCKS_PER_SCANLINE as usize {
+            for y in window.ystart as usize..(window.ystart + window.height) as usize {
+                let index = y * tia::CLOCKS_PER_SCANLINE as usize + x;
+                let rgb = tv_standard.palette_rgb(frame[index]);
 
-                    if audio_device.size() == 0 {
-                        println!("audio buffer underrun");
-                    }
+                pixels[index * 3 + 0] = rgb.0;
+                pixels[index * 3 + 1] = rgb.1;
+                pixels[index * 3 + 2] = rgb.2;
 
-                    let samples = audio_converter.convert(&mut atari.tia.audio);
-                    audio_device.queue_audio(samples).unwrap();
+            }
+        }
 
-                    while audio_device.size() > 2048 {
-                        thread::sleep(Duration::from_micros(1));
-                    }
+        texture.update(None, &pixels, 3 * tia::CLOCKS_PER_SCANLINE as usize).unwrap();
+        canvas.copy(&texture, Rect::new(0, window.ystart as i32, tia::CLOCKS_PER_SCANLINE as u32, window.height as u32), None).unwrap();
+        canvas.present();
 
-                    frame_num += 1;
-                }
+        if !rewinding {
+            if audio_device.size() == 0 {
+                println!("audio buffer underrun");
             }
 
-            cpu.cycle(!wsync);
+            audio_device.queue_audio(system.audio()).unwrap();
+
+            while audio_device.size() > 2048 {
+                thread::sleep(Duration::from_micros(1));
+            }
         }
+
+        frame_num += 1;
+    }
+
+    if let Some((recorder, path)) = recording {
+        save_movie(recorder, &path);
+    }
+
+    if let Some(capturing) = capture {
+        capturing.finish(&mut system).expect("could not finish capture");
     }
-}
\ No newline at end of file
+}
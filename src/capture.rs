@@ -0,0 +1,181 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::{FrameView, System};
+
+const WAV_HEADER_SIZE: u32 = 44;
+
+// y4m planes for a fixed size, frames of another size are cropped or padded with black
+struct VideoFormat {
+    width: usize,
+    height: usize,
+    samples_per_frame: usize
+}
+
+// every frame to a YUV4MPEG2 stream and the mixed audio to an 8-bit mono WAV. Each frame gets exactly
+// one frame's worth of samples at the nominal line count, so the two streams stay in sync however long it runs
+pub struct Capture<W: Write + Seek> {
+    video: W,
+    audio: W,
+    view: FrameView,
+    format: Option<VideoFormat>,
+    samples: u32
+}
+
+impl<W: Write + Seek> Capture<W> {
+    pub fn new(system: &mut System, view: FrameView, video: W, audio: W) -> Self {
+        system.set_audio_capture(true);
+        Capture { video, audio, view, format: None, samples: 0 }
+    }
+
+    // the headers are written once the first frame has settled the size and TV standard
+    fn start(&mut self, system: &System) -> io::Result<()> {
+        let tv_standard = system.tv_standard();
+        let (width, height, _) = system.frame_rgb(self.view);
+        let sample_rate = tv_standard.audio_clock_hz();
+        let samples_per_frame = 2 * tv_standard.scanlines() as usize;
+
+        writeln!(self.video, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444", width, height, sample_rate, samples_per_frame)?;
+
+        self.audio.write_all(b"RIFF")?;
+        self.audio.write_all(&(WAV_HEADER_SIZE - 8).to_le_bytes())?;
+        self.audio.write_all(b"WAVEfmt ")?;
+        self.audio.write_all(&16u32.to_le_bytes())?;
+        self.audio.write_all(&1u16.to_le_bytes())?;
+        self.audio.write_all(&1u16.to_le_bytes())?;
+        self.audio.write_all(&sample_rate.to_le_bytes())?;
+        self.audio.write_all(&sample_rate.to_le_bytes())?;
+        self.audio.write_all(&1u16.to_le_bytes())?;
+        self.audio.write_all(&8u16.to_le_bytes())?;
+        self.audio.write_all(b"data")?;
+        self.audio.write_all(&0u32.to_le_bytes())?;
+
+        self.format = Some(VideoFormat { width, height, samples_per_frame });
+        Ok(())
+    }
+
+    // call after every frame
+    pub fn frame(&mut self, system: &mut System) -> io::Result<()> {
+        if self.format.is_none() {
+            self.start(system)?;
+        }
+
+        let format = self.format.as_ref().unwrap();
+
+        let (width, height, rgb) = system.frame_rgb(self.view);
+        let mut planes: [Vec<u8>; 3] = std::array::from_fn(|_| Vec::with_capacity(format.width * format.height));
+
+        for y in 0..format.height {
+            for x in 0..format.width {
+                let (r, g, b) = if x < width && y < height {
+                    let i = (y * width + x) * 3;
+                    (rgb[i] as i32, rgb[i + 1] as i32, rgb[i + 2] as i32)
+                } else {
+                    (0, 0, 0)
+                };
+
+                // BT.601 studio range
+                planes[0].push((((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8);
+                planes[1].push((((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8);
+                planes[2].push((((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8);
+            }
+        }
+
+        self.video.write_all(b"FRAME\n")?;
+
+        for plane in &planes {
+            self.video.write_all(plane)?;
+        }
+
+        // stretch the audio clocks of a frame that wasn't the nominal length
+        let captured = system.take_audio_capture();
+        let samples = (0..format.samples_per_frame)
+            .map(|i| captured.get(i * captured.len() / format.samples_per_frame).copied().unwrap_or(128))
+            .collect::<Vec<u8>>();
+
+        self.audio.write_all(&samples)?;
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    // fills in the WAV sizes and stops capturing audio
+    pub fn finish(mut self, system: &mut System) -> io::Result<()> {
+        system.set_audio_capture(false);
+
+        if self.format.is_none() {
+            self.start(system)?;
+        }
+
+        self.audio.seek(SeekFrom::Start(4))?;
+        self.audio.write_all(&(WAV_HEADER_SIZE - 8 + self.samples).to_le_bytes())?;
+        self.audio.seek(SeekFrom::Start(WAV_HEADER_SIZE as u64 - 4))?;
+        self.audio.write_all(&self.samples.to_le_bytes())?;
+        self.audio.flush()?;
+        self.video.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::TvStandard;
+
+    // 262 line frames: VSYNC for three lines, then 256 and 3 more
+    const PROGRAM: [u8; 31] = [
+        0xA9, 0x02, 0x85, 0x00, // LDA #2, STA VSYNC
+        0x85, 0x02, 0x85, 0x02, 0x85, 0x02, // STA WSYNC x3
+        0xA9, 0x00, 0x85, 0x00, // LDA #0, STA VSYNC
+        0xA2, 0, 0x85, 0x02, 0xCA, 0xD0, 0xFB, // 256 lines
+        0xA2, 3, 0x85, 0x02, 0xCA, 0xD0, 0xFB, // 3 lines
+        0x4C, 0x00, 0xF0 // JMP $F000
+    ];
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn y4m_and_wav_streams() {
+        let mut system = System::new(System::test_rom(&PROGRAM));
+        let (mut video, mut audio) = (Cursor::new(Vec::new()), Cursor::new(Vec::new()));
+
+        // settle on whole frames before capturing
+        system.run_frame();
+        system.run_frame();
+
+        let mut capture = Capture::new(&mut system, FrameView::default(), &mut video, &mut audio);
+
+        for _ in 0..3 {
+            system.run_frame();
+            capture.frame(&mut system).unwrap();
+        }
+
+        capture.finish(&mut system).unwrap();
+
+        let (video, audio) = (video.into_inner(), audio.into_inner());
+        let sample_rate = TvStandard::Ntsc.audio_clock_hz();
+        let samples = 3 * 2 * 262;
+
+        let header = format!("YUV4MPEG2 W160 H210 F{}:524 Ip A1:1 C444\n", sample_rate);
+        let frame_size = "FRAME\n".len() + 3 * 160 * 210;
+        assert_eq!(&video[..header.len()], header.as_bytes());
+        assert_eq!(video.len(), header.len() + 3 * frame_size);
+
+        for frame in 0..3 {
+            let start = header.len() + frame * frame_size;
+            assert_eq!(&video[start..start + 6], b"FRAME\n", "frame {}", frame);
+        }
+
+        assert_eq!(audio.len(), WAV_HEADER_SIZE as usize + samples);
+        assert_eq!(&audio[0..4], b"RIFF");
+        assert_eq!(u32_at(&audio, 4), WAV_HEADER_SIZE - 8 + samples as u32);
+        assert_eq!(&audio[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&audio, 16), 16);
+        assert_eq!(audio[20..24], [1, 0, 1, 0]);
+        assert_eq!(u32_at(&audio, 24), sample_rate);
+        assert_eq!(u32_at(&audio, 28), sample_rate);
+        assert_eq!(audio[32..36], [1, 0, 8, 0]);
+        assert_eq!(&audio[36..40], b"data");
+        assert_eq!(u32_at(&audio, 40), samples as u32);
+    }
+}
@@ -0,0 +1,557 @@
+use crate::{state::{StateError, StateReader, StateWriter}, tia::Beam};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Port {
+    Left,
+    Right
+}
+
+// input from the frontend, each controller ignores the events it has no use for
+#[derive(Clone, Copy, Debug)]
+pub enum ControllerEvent {
+    Up(bool),
+    Down(bool),
+    Left(bool),
+    Right(bool),
+    Fire(bool),
+    Button(usize, bool),
+    Paddle(usize, u8),
+    Key(usize, bool),
+    Motion(i32, i32),
+    Aim(u16, u16)
+}
+
+// levels on the controller side of a port
+#[derive(Clone, Copy, Debug)]
+pub struct PortPins {
+    pub data: u8, // pins 1-4 in bits 0-3
+    pub pots: [Option<u8>; 2], // pins 5 and 9, `None` never charges
+    pub fire: bool // pin 6 pulled low
+}
+
+impl PortPins {
+    fn new() -> Self {
+        PortPins { data: 0x0F, pots: [None; 2], fire: false }
+    }
+}
+
+pub trait Controller {
+    fn event(&mut self, event: ControllerEvent);
+
+    // `output` holds the levels the console drives onto pins 1-4, `beam` is where the TIA is drawing
+    fn pins(&mut self, output: u8, beam: Beam) -> PortPins;
+
+    // (color clock, scanline) a light gun is pointed at
+    fn light_target(&self) -> Option<(u16, u16)> {
+        None
+    }
+
+    // held inputs and counters, saved with the console so a loaded state or movie carries on from them
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        match data.is_empty() {
+            true => Ok(()),
+            false => Err(StateError::Invalid)
+        }
+    }
+}
+
+macro_rules! state_fields {
+    ($($($field:ident).+),*) => {
+        fn save_state(&self) -> Vec<u8> {
+            let mut writer = StateWriter::bare();
+            $(writer.put(&self.$($field).+);)*
+            writer.finish()
+        }
+
+        fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+            let mut reader = StateReader::bare(data);
+            $(self.$($field).+ = reader.get()?;)*
+            reader.finish()
+        }
+    };
+}
+
+fn set_low(data: &mut u8, bit: u8, low: bool) {
+    if low {
+        *data &= !(1 << bit);
+    } else {
+        *data |= 1 << bit;
+    }
+}
+
+pub struct Joystick {
+    data: u8,
+    fire: bool
+}
+
+impl Joystick {
+    pub fn new() -> Self {
+        Joystick { data: 0x0F, fire: false }
+    }
+}
+
+impl Default for Joystick {
+    fn default() -> Self {
+        Joystick::new()
+    }
+}
+
+impl Controller for Joystick {
+    fn event(&mut self, event: ControllerEvent) {
+        match event {
+            ControllerEvent::Up(pressed) => set_low(&mut self.data, 0, pressed),
+            ControllerEvent::Down(pressed) => set_low(&mut self.data, 1, pressed),
+            ControllerEvent::Left(pressed) => set_low(&mut self.data, 2, pressed),
+            ControllerEvent::Right(pressed) => set_low(&mut self.data, 3, pressed),
+            ControllerEvent::Fire(pressed) => self.fire = pressed,
+            _ => ()
+        }
+    }
+
+    fn pins(&mut self, _output: u8, _beam: Beam) -> PortPins {
+        PortPins { data: self.data, fire: self.fire, ..PortPins::new() }
+    }
+
+    state_fields!(data, fire);
+}
+
+// a pair of paddles, each with its fire button on one of the data pins
+pub struct Paddles {
+    positions: [u8; 2],
+    data: u8
+}
+
+impl Paddles {
+    pub fn new() -> Self {
+        Paddles { positions: [128; 2], data: 0x0F }
+    }
+}
+
+impl Default for Paddles {
+    fn default() -> Self {
+        Paddles::new()
+    }
+}
+
+impl Controller for Paddles {
+    fn event(&mut self, event: ControllerEvent) {
+        match event {
+            ControllerEvent::Paddle(index @ 0..=1, position) => self.positions[index] = position,
+            ControllerEvent::Button(index @ 0..=1, pressed) => set_low(&mut self.data, 3 - index as u8, pressed),
+            _ => ()
+        }
+    }
+
+    fn pins(&mut self, _output: u8, _beam: Beam) -> PortPins {
+        PortPins { data: self.data, pots: [Some(self.positions[0]), Some(self.positions[1])], ..PortPins::new() }
+    }
+
+    state_fields!(positions, data);
+}
+
+// 12 keys in rows of three: 1 2 3, 4 5 6, 7 8 9, * 0 #
+pub struct Keypad {
+    keys: [bool; 12]
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Keypad { keys: [false; 12] }
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Keypad::new()
+    }
+}
+
+impl Controller for Keypad {
+    fn event(&mut self, event: ControllerEvent) {
+        if let ControllerEvent::Key(index @ 0..=11, pressed) = event {
+            self.keys[index] = pressed;
+        }
+    }
+
+    // the console pulls a row low on pins 1-4 and a pressed key in that row grounds its column
+    fn pins(&mut self, output: u8, _beam: Beam) -> PortPins {
+        let mut columns = [false; 3];
+
+        for row in 0..4 {
+            if output & (1 << row) == 0 {
+                for (column, pressed) in columns.iter_mut().enumerate() {
+                    *pressed |= self.keys[row * 3 + column];
+                }
+            }
+        }
+
+        PortPins {
+            data: 0x0F,
+            pots: [
+                if columns[0] { None } else { Some(0) },
+                if columns[1] { None } else { Some(0) }
+            ],
+            fire: columns[2]
+        }
+    }
+
+    state_fields!(keys);
+}
+
+const GRAY_CODE: [u8; 4] = [0b00, 0b01, 0b11, 0b10];
+
+// mouse units per step of a driving controller
+const DRIVING_SENSITIVITY: i32 = 8;
+
+// the most quadrature steps a pointing device produces in one frame
+const MAX_STEPS_PER_FRAME: i32 = 64;
+
+// games read the driving controller once a frame, so it turns at most one step per frame
+pub struct DrivingController {
+    position: u8,
+    motion: i32,
+    left: bool,
+    right: bool,
+    frame: u32,
+    fire: bool
+}
+
+impl DrivingController {
+    pub fn new() -> Self {
+        DrivingController { position: 0, motion: 0, left: false, right: false, frame: 0, fire: false }
+    }
+}
+
+impl Default for DrivingController {
+    fn default() -> Self {
+        DrivingController::new()
+    }
+}
+
+impl Controller for DrivingController {
+    fn event(&mut self, event: ControllerEvent) {
+        match event {
+            ControllerEvent::Left(pressed) => self.left = pressed,
+            ControllerEvent::Right(pressed) => self.right = pressed,
+            ControllerEvent::Fire(pressed) => self.fire = pressed,
+            ControllerEvent::Motion(dx, _) => self.motion += dx,
+            _ => ()
+        }
+    }
+
+    fn pins(&mut self, _output: u8, beam: Beam) -> PortPins {
+        if beam.frame != self.frame {
+            self.frame = beam.frame;
+
+            if self.left || self.motion <= -DRIVING_SENSITIVITY {
+                self.position = self.position.wrapping_sub(1);
+                self.motion = (self.motion + DRIVING_SENSITIVITY).min(0);
+            } else if self.right || self.motion >= DRIVING_SENSITIVITY {
+                self.position = self.position.wrapping_add(1);
+                self.motion = (self.motion - DRIVING_SENSITIVITY).max(0);
+            }
+        }
+
+        PortPins { data: 0b1100 | GRAY_CODE[self.position as usize & 0b11], fire: self.fire, ..PortPins::new() }
+    }
+
+    state_fields!(position, motion, left, right, frame, fire);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PointingDeviceKind {
+    TrakBall,
+    AmigaMouse,
+    AtariMouse
+}
+
+// CX-22 trak-ball and Amiga/ST mice, which put two quadrature signals per axis on the data pins
+pub struct PointingDevice {
+    kind: PointingDeviceKind,
+    motion: [i32; 2],
+    steps: [i32; 2],
+    emitted: [i32; 2],
+    counts: [u8; 2],
+    frame: u32,
+    fire: bool
+}
+
+impl PointingDevice {
+    pub fn new(kind: PointingDeviceKind) -> Self {
+        PointingDevice { kind, motion: [0; 2], steps: [0; 2], emitted: [0; 2], counts: [0; 2], frame: 0, fire: false }
+    }
+
+    fn data(&self) -> u8 {
+        let (h, v) = (self.counts[0] as usize, self.counts[1] as usize);
+
+        match self.kind {
+            PointingDeviceKind::TrakBall => {
+                let left = if self.steps[0] < 0 { 0b0001 } else { 0 };
+                let down = if self.steps[1] > 0 { 0b1000 } else { 0 };
+
+                [0b00, 0b10][h & 1] | [0b0100, 0b0000][v & 1] | left | down
+            }
+            PointingDeviceKind::AmigaMouse => [0b0000, 0b0010, 0b1010, 0b1000][h & 3] | [0b0000, 0b0100, 0b0101, 0b0001][v & 3],
+            PointingDeviceKind::AtariMouse => [0b0000, 0b0001, 0b0011, 0b0010][h & 3] | [0b0000, 0b0100, 0b1100, 0b1000][v & 3]
+        }
+    }
+}
+
+impl Controller for PointingDevice {
+    fn event(&mut self, event: ControllerEvent) {
+        match event {
+            ControllerEvent::Motion(dx, dy) => {
+                self.motion[0] += dx;
+                self.motion[1] += dy;
+            }
+            ControllerEvent::Fire(pressed) => self.fire = pressed,
+            _ => ()
+        }
+    }
+
+    // each frame's motion is spread over its scanlines so polling kernels see every step
+    fn pins(&mut self, _output: u8, beam: Beam) -> PortPins {
+        if beam.frame != self.frame {
+            self.frame = beam.frame;
+
+            for axis in 0..2 {
+                self.steps[axis] = self.motion[axis].clamp(-MAX_STEPS_PER_FRAME, MAX_STEPS_PER_FRAME);
+                self.motion[axis] -= self.steps[axis];
+                self.emitted[axis] = 0;
+            }
+        }
+
+        let scanlines = beam.tv_standard.scanlines();
+
+        for axis in 0..2 {
+            let due = self.steps[axis] * beam.scanline.min(scanlines) as i32 / scanlines as i32;
+
+            while self.emitted[axis] != due {
+                let step = (due - self.emitted[axis]).signum();
+                self.counts[axis] = self.counts[axis].wrapping_add(step as u8);
+                self.emitted[axis] += step;
+            }
+        }
+
+        PortPins { data: self.data(), fire: self.fire, ..PortPins::new() }
+    }
+
+    state_fields!(motion, steps, emitted, counts, frame, fire);
+}
+
+// XG-1 light gun, the trigger is on pin 1 and the light sensor on pin 6 is handled by the TIA
+pub struct LightGun {
+    aim: (u16, u16),
+    trigger: bool
+}
+
+impl LightGun {
+    pub fn new() -> Self {
+        LightGun { aim: (0, 0), trigger: false }
+    }
+}
+
+impl Default for LightGun {
+    fn default() -> Self {
+        LightGun::new()
+    }
+}
+
+impl Controller for LightGun {
+    fn event(&mut self, event: ControllerEvent) {
+        match event {
+            ControllerEvent::Aim(x, y) => self.aim = (x, y),
+            ControllerEvent::Fire(pressed) => self.trigger = pressed,
+            _ => ()
+        }
+    }
+
+    fn pins(&mut self, _output: u8, _beam: Beam) -> PortPins {
+        PortPins { data: if self.trigger { 0x0E } else { 0x0F }, ..PortPins::new() }
+    }
+
+    fn light_target(&self) -> Option<(u16, u16)> {
+        Some(self.aim)
+    }
+
+    state_fields!(aim, trigger);
+}
+
+// button B is the usual fire button, button C pulls pin 9 low
+pub struct Genesis {
+    joystick: Joystick,
+    button_c: bool
+}
+
+impl Genesis {
+    pub fn new() -> Self {
+        Genesis { joystick: Joystick::new(), button_c: false }
+    }
+}
+
+impl Default for Genesis {
+    fn default() -> Self {
+        Genesis::new()
+    }
+}
+
+impl Controller for Genesis {
+    fn event(&mut self, event: ControllerEvent) {
+        match event {
+            ControllerEvent::Button(0, pressed) => self.button_c = pressed,
+            _ => self.joystick.event(event)
+        }
+    }
+
+    fn pins(&mut self, output: u8, beam: Beam) -> PortPins {
+        PortPins {
+            pots: [None, if self.button_c { None } else { Some(0) }],
+            ..self.joystick.pins(output, beam)
+        }
+    }
+
+    state_fields!(joystick.data, joystick.fire, button_c);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TvStandard;
+
+    fn beam(frame: u32, scanline: u16) -> Beam {
+        Beam { clock: 0, frame, scanline, color_clock: 0, tv_standard: TvStandard::Ntsc }
+    }
+
+    fn pal_beam(frame: u32, scanline: u16) -> Beam {
+        Beam { tv_standard: TvStandard::Pal, ..beam(frame, scanline) }
+    }
+
+    #[test]
+    fn genesis_button_c_grounds_pin_9() {
+        let mut genesis = Genesis::new();
+        let pins = genesis.pins(0x0F, beam(0, 0));
+        assert_eq!((pins.data, pins.pots, pins.fire), (0x0F, [None, Some(0)], false));
+
+        genesis.event(ControllerEvent::Button(0, true));
+        genesis.event(ControllerEvent::Fire(true));
+        genesis.event(ControllerEvent::Up(true));
+        let pins = genesis.pins(0x0F, beam(0, 0));
+        assert_eq!((pins.data, pins.pots, pins.fire), (0x0E, [None, None], true));
+
+        genesis.event(ControllerEvent::Button(0, false));
+        assert_eq!(genesis.pins(0x0F, beam(0, 0)).pots, [None, Some(0)]);
+    }
+
+    // pots read `None` (never charging) and fire reads low for a grounded column
+    fn keypad_columns(pins: PortPins) -> [bool; 3] {
+        [pins.pots[0].is_none(), pins.pots[1].is_none(), pins.fire]
+    }
+
+    #[test]
+    fn keypad_scans_the_driven_row() {
+        let mut keypad = Keypad::new();
+        keypad.event(ControllerEvent::Key(4, true)); // 5, middle column of the second row
+        keypad.event(ControllerEvent::Key(11, true)); // #, right column of the last row
+
+        let expected = [[false; 3], [false, true, false], [false; 3], [false, false, true]];
+
+        for (row, columns) in expected.iter().enumerate() {
+            let pins = keypad.pins(0x0F & !(1 << row), beam(0, 0));
+            assert_eq!(pins.data, 0x0F);
+            assert_eq!(keypad_columns(pins), *columns, "row {}", row);
+        }
+
+        assert_eq!(keypad_columns(keypad.pins(0x0F, beam(0, 0))), [false; 3]);
+        assert_eq!(keypad_columns(keypad.pins(0x00, beam(0, 0))), [false, true, true]);
+
+        keypad.event(ControllerEvent::Key(4, false));
+        assert_eq!(keypad_columns(keypad.pins(0x0D, beam(0, 0))), [false; 3]);
+    }
+
+    #[test]
+    fn pointing_device_spreads_steps_over_the_frame_of_its_standard() {
+        let mut mouse = PointingDevice::new(PointingDeviceKind::AtariMouse);
+        mouse.event(ControllerEvent::Motion(8, 0));
+
+        mouse.pins(0x0F, pal_beam(1, 0));
+        assert_eq!(mouse.counts[0], 0);
+        mouse.pins(0x0F, pal_beam(1, 262));
+        assert_eq!(mouse.counts[0], 6);
+        mouse.pins(0x0F, pal_beam(1, 312));
+        assert_eq!(mouse.counts[0], 8);
+    }
+
+    #[test]
+    fn driving_controller_steps_through_gray_code() {
+        let mut driving = DrivingController::new();
+        driving.event(ControllerEvent::Right(true));
+
+        let mut codes = Vec::new();
+        for frame in 1..=5 {
+            codes.push(driving.pins(0x0F, beam(frame, 0)).data);
+            assert_eq!(driving.pins(0x0F, beam(frame, 100)).data, codes[codes.len() - 1], "one step a frame");
+        }
+        assert_eq!(codes, [0b1101, 0b1111, 0b1110, 0b1100, 0b1101]);
+
+        driving.event(ControllerEvent::Right(false));
+        driving.event(ControllerEvent::Left(true));
+
+        let codes: Vec<u8> = (6..=10).map(|frame| driving.pins(0x0F, beam(frame, 0)).data).collect();
+        assert_eq!(codes, [0b1100, 0b1110, 0b1111, 0b1101, 0b1100]);
+    }
+
+    #[test]
+    fn driving_controller_turns_with_motion() {
+        let mut driving = DrivingController::new();
+        driving.event(ControllerEvent::Motion(2 * DRIVING_SENSITIVITY, 0));
+
+        let codes: Vec<u8> = (1..=3).map(|frame| driving.pins(0x0F, beam(frame, 0)).data & 0b11).collect();
+        assert_eq!(codes, [0b01, 0b11, 0b11]);
+
+        driving.event(ControllerEvent::Motion(-DRIVING_SENSITIVITY, 0));
+        assert_eq!(driving.pins(0x0F, beam(4, 0)).data & 0b11, 0b01);
+    }
+
+    // pins 1-4 at five points spread over one NTSC frame of motion
+    fn quadrature(kind: PointingDeviceKind, dx: i32, dy: i32) -> Vec<u8> {
+        let mut device = PointingDevice::new(kind);
+        device.event(ControllerEvent::Motion(dx, dy));
+
+        [0, 66, 131, 197, 262].into_iter().map(|scanline| device.pins(0x0F, beam(1, scanline)).data).collect()
+    }
+
+    #[test]
+    fn trak_ball_quadrature() {
+        // pin 2 toggles per horizontal step and pin 3 per vertical step, pins 1 and 4 give the direction
+        assert_eq!(quadrature(PointingDeviceKind::TrakBall, 4, -4), [0b0100, 0b0010, 0b0100, 0b0010, 0b0100]);
+        assert_eq!(quadrature(PointingDeviceKind::TrakBall, -4, 4), [0b1101, 0b1011, 0b1101, 0b1011, 0b1101]);
+    }
+
+    #[test]
+    fn amiga_mouse_quadrature() {
+        assert_eq!(quadrature(PointingDeviceKind::AmigaMouse, 4, 4), [0b0000, 0b0110, 0b1111, 0b1001, 0b0000]);
+        assert_eq!(quadrature(PointingDeviceKind::AmigaMouse, -4, 0), [0b0000, 0b1000, 0b1010, 0b0010, 0b0000]);
+    }
+
+    #[test]
+    fn atari_mouse_quadrature() {
+        assert_eq!(quadrature(PointingDeviceKind::AtariMouse, 4, 4), [0b0000, 0b0101, 0b1111, 0b1010, 0b0000]);
+        assert_eq!(quadrature(PointingDeviceKind::AtariMouse, -4, 0), [0b0000, 0b0010, 0b0011, 0b0001, 0b0000]);
+    }
+
+    #[test]
+    fn light_gun_trigger_and_aim() {
+        let mut gun = LightGun::new();
+        gun.event(ControllerEvent::Aim(100, 60));
+        assert_eq!(gun.light_target(), Some((100, 60)));
+        assert_eq!(gun.pins(0x0F, beam(0, 0)).data, 0x0F);
+
+        gun.event(ControllerEvent::Fire(true));
+        let pins = gun.pins(0x0F, beam(0, 0));
+        assert_eq!((pins.data, pins.fire), (0x0E, false));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{AddressBus, Mnemonic, sr_flags, address_space, Instruction, AddressMode, AccessType, decode};
+use crate::{power_on::Rng, state::{StateError, StateReader, StateWriter}, AddressBus, Mnemonic, sr_flags, address_space, Instruction, AddressMode, AccessType, decode, decode::try_decode};
 
 pub struct MOS6502<T: AddressBus> {
     pc: u16,
@@ -14,10 +14,58 @@ pub struct MOS6502<T: AddressBus> {
     addr_invalid: bool,
     ptr: u16,
     ptr_invalid: bool,
+    opcode: u8,
     instruction: Instruction,
+    wrote: bool,
     bus: T
 }
 
+// everything a cycle can change, so a cycle halted by RDY can be undone
+#[derive(Clone, Copy)]
+struct Snapshot {
+    pc: u16,
+    sr: u8,
+    sp: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    tmp: u8,
+    cycle: u8,
+    cycles: u32,
+    addr: u16,
+    addr_invalid: bool,
+    ptr: u16,
+    ptr_invalid: bool,
+    opcode: u8,
+    instruction: Instruction
+}
+
+// the cycle an instruction ends on, counting the opcode fetch as 1 and including page crossings and taken branches
+fn last_cycle(instruction: Instruction) -> u8 {
+    match instruction.mode {
+        AddressMode::Implied | AddressMode::Accumulator | AddressMode::Immediate => 2,
+        AddressMode::Absolute(_) if matches!(instruction.name, Mnemonic::Jmp) => 3,
+        AddressMode::Absolute(AccessType::ReadModifyWrite) => 6,
+        AddressMode::Absolute(_) => 4,
+        AddressMode::ZeroPage(AccessType::ReadModifyWrite) => 5,
+        AddressMode::ZeroPage(_) => 3,
+        AddressMode::ZeroPageIndexedX(AccessType::ReadModifyWrite) | AddressMode::ZeroPageIndexedY(AccessType::ReadModifyWrite) => 6,
+        AddressMode::ZeroPageIndexedX(_) | AddressMode::ZeroPageIndexedY(_) => 4,
+        AddressMode::AbsoluteIndexedX(AccessType::ReadModifyWrite) | AddressMode::AbsoluteIndexedY(AccessType::ReadModifyWrite) => 7,
+        AddressMode::AbsoluteIndexedX(_) | AddressMode::AbsoluteIndexedY(_) => 5,
+        AddressMode::Relative => 4,
+        AddressMode::XIndexedIndirect(AccessType::ReadModifyWrite) | AddressMode::IndirectIndexedY(AccessType::ReadModifyWrite) => 8,
+        AddressMode::XIndexedIndirect(_) | AddressMode::IndirectIndexedY(_) => 6,
+        AddressMode::Indirect => 5,
+        AddressMode::Special => match instruction.name {
+            Mnemonic::Pha | Mnemonic::Php => 3,
+            Mnemonic::Pla | Mnemonic::Plp => 4,
+            Mnemonic::Jsr | Mnemonic::Rts | Mnemonic::Rti => 6,
+            _ => 7
+        }
+    }
+}
+
 impl<T: AddressBus> MOS6502<T> {
     pub fn new(bus: T) -> Self {
         MOS6502 {
@@ -34,7 +82,9 @@ impl<T: AddressBus> MOS6502<T> {
             addr_invalid: false,
             ptr: 0,
             ptr_invalid: false,
+            opcode: 0,
             instruction: Instruction { name: Mnemonic::Brk, mode: AddressMode::Implied },
+            wrote: false,
             bus
         }
     }
@@ -43,8 +93,55 @@ impl<T: AddressBus> MOS6502<T> {
         &mut self.bus
     }
 
+    pub fn bus(&self) -> &T {
+        &self.bus
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.wrote = true;
+        self.bus.write(addr, value);
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            sr: self.sr,
+            sp: self.sp,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            tmp: self.tmp,
+            cycle: self.cycle,
+            cycles: self.cycles,
+            addr: self.addr,
+            addr_invalid: self.addr_invalid,
+            ptr: self.ptr,
+            ptr_invalid: self.ptr_invalid,
+            opcode: self.opcode,
+            instruction: self.instruction
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.pc = snapshot.pc;
+        self.sr = snapshot.sr;
+        self.sp = snapshot.sp;
+        self.a = snapshot.a;
+        self.x = snapshot.x;
+        self.y = snapshot.y;
+        self.tmp = snapshot.tmp;
+        self.cycle = snapshot.cycle;
+        self.cycles = snapshot.cycles;
+        self.addr = snapshot.addr;
+        self.addr_invalid = snapshot.addr_invalid;
+        self.ptr = snapshot.ptr;
+        self.ptr_invalid = snapshot.ptr_invalid;
+        self.opcode = snapshot.opcode;
+        self.instruction = snapshot.instruction;
+    }
+
     fn push(&mut self, value: u8) {
-        self.bus.write(address_space::STACK + self.sp as u16, value);
+        self.write(address_space::STACK + self.sp as u16, value);
         self.sp = self.sp.wrapping_sub(1);
     }
 
@@ -54,22 +151,83 @@ impl<T: AddressBus> MOS6502<T> {
         result
     }
 
+    pub(crate) fn randomize(&mut self, rng: &mut Rng) {
+        self.a = rng.next_u8();
+        self.x = rng.next_u8();
+        self.y = rng.next_u8();
+        self.sp = rng.next_u8();
+        self.sr = rng.next_u8();
+    }
+
+    // the bus is saved by its owner
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.pc);
+        writer.put(&self.sr);
+        writer.put(&self.sp);
+        writer.put(&self.a);
+        writer.put(&self.x);
+        writer.put(&self.y);
+        writer.put(&self.tmp);
+        writer.put(&self.cycle);
+        writer.put(&self.cycles);
+        writer.put(&self.addr);
+        writer.put(&self.addr_invalid);
+        writer.put(&self.ptr);
+        writer.put(&self.ptr_invalid);
+        writer.put(&self.opcode);
+    }
+
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.pc = reader.get()?;
+        self.sr = reader.get()?;
+        self.sp = reader.get()?;
+        self.a = reader.get()?;
+        self.x = reader.get()?;
+        self.y = reader.get()?;
+        self.tmp = reader.get()?;
+        self.cycle = reader.get()?;
+        self.cycles = reader.get()?;
+        self.addr = reader.get()?;
+        self.addr_invalid = reader.get()?;
+        self.ptr = reader.get()?;
+        self.ptr_invalid = reader.get()?;
+        self.opcode = reader.get()?;
+        self.instruction = try_decode(self.opcode).ok_or(StateError::Invalid)?;
+
+        if self.cycle == 0 || self.cycle > last_cycle(self.instruction) {
+            return Err(StateError::Invalid);
+        }
+
+        Ok(())
+    }
+
     pub fn reset(&mut self) {
         let lo = self.bus.read(address_space::RES_VECTOR) as u16;
         let hi = self.bus.read(address_space::RES_VECTOR + 1) as u16;
         self.pc = hi << 8 | lo;
     }
 
+    // every cycle that doesn't write is a read, and RDY low halts the 6502 on reads, so the cycle
+    // is run for its bus side effects and then undone until RDY goes high
     pub fn cycle(&mut self, rdy: bool) {
+        let snapshot = if rdy { None } else { Some(self.snapshot()) };
+        self.wrote = false;
+        self.step();
+
+        if let Some(snapshot) = snapshot {
+            if !self.wrote {
+                self.restore(snapshot);
+            }
+        }
+    }
+
+    fn step(&mut self) {
         match self.cycle {
             1 => {
-                if !rdy {
-                    return;
-                }
-
                 let opcode = self.bus.read(self.pc);
                 //println!("0x{:04X}: opcode: 0x{:02X} {:?}, cycles: {}", self.pc, opcode, cpu_6502_decode::decode(opcode), self.cycles);
                 //println!("A: 0x{:02X}, X: 0x{:02X}, Y: 0x{:02X}, SP: 0x{:02X}, SR: 0x{:02X}", self.a, self.x, self.y, self.sp, self.sr);
+                self.opcode = opcode;
                 self.instruction = decode(opcode);
                 self.pc += 1;
             },
@@ -268,12 +426,12 @@ impl<T: AddressBus> MOS6502<T> {
                     self.tmp = self.bus.read(self.addr);
                 }
                 6 => {
-                    self.bus.write(self.addr, self.tmp); // QUIRK
+                    self.write(self.addr, self.tmp); // QUIRK
                     self.tmp = self.execute_rmw(self.tmp);
                 }
                 7 => {
                     self.cycle = 0;
-                    self.bus.write(self.addr, self.tmp);
+                    self.write(self.addr, self.tmp);
                 }
                 _ => panic!()
             },
@@ -329,12 +487,12 @@ impl<T: AddressBus> MOS6502<T> {
                     self.tmp = self.bus.read(self.addr);
                 }
                 4 => {
-                    self.bus.write(self.addr, self.tmp); // QUIRK
+                    self.write(self.addr, self.tmp); // QUIRK
                     self.tmp = self.execute_rmw(self.tmp);
                 }
                 5 => {
                     self.cycle = 0;
-                    self.bus.write(self.addr, self.tmp);
+                    self.write(self.addr, self.tmp);
                 }
                 _ => panic!()
             },
@@ -606,12 +764,12 @@ impl<T: AddressBus> MOS6502<T> {
                     self.tmp = self.bus.read(self.addr);
                 }
                 5 => {
-                    self.bus.write(self.addr, self.tmp); // QUIRK
+                    self.write(self.addr, self.tmp); // QUIRK
                     self.tmp = self.execute_rmw(self.tmp);
                 }
                 6 => {
                     self.cycle = 0;
-                    self.bus.write(self.addr, self.tmp);
+                    self.write(self.addr, self.tmp);
                 }
                 _ => panic!()
             },
@@ -708,12 +866,12 @@ impl<T: AddressBus> MOS6502<T> {
                     self.tmp = self.bus.read(self.addr);
                 }
                 5 => { 
-                    self.bus.write(self.addr, self.tmp); // QUIRK
+                    self.write(self.addr, self.tmp); // QUIRK
                     self.tmp = self.execute_rmw(self.tmp);
                 }
                 6 => {
                     self.cycle = 0;
-                    self.bus.write(self.addr, self.tmp);
+                    self.write(self.addr, self.tmp);
                 }
                 _ => panic!()
             },
@@ -799,9 +957,9 @@ impl<T: AddressBus> MOS6502<T> {
 
     fn execute_write(&mut self, addr: u16) {
         match self.instruction.name {
-            Mnemonic::Sta => self.bus.write(addr, self.a),
-            Mnemonic::Stx => self.bus.write(addr, self.x),
-            Mnemonic::Sty => self.bus.write(addr, self.y),
+            Mnemonic::Sta => self.write(addr, self.a),
+            Mnemonic::Stx => self.write(addr, self.x),
+            Mnemonic::Sty => self.write(addr, self.y),
             _ => panic!()
         }
     }
@@ -909,4 +1067,103 @@ impl<T: AddressBus> MOS6502<T> {
         self.update_flags(c);
         c
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Ram([u8; 0x10000]);
+
+    impl AddressBus for Ram {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.0[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, value: u8) {
+            self.0[addr as usize] = value;
+        }
+    }
+
+    fn cpu(program: &[u8]) -> MOS6502<Ram> {
+        let mut ram = Ram([0xEA; 0x10000]);
+        ram.0[0x1000..0x1000 + program.len()].copy_from_slice(program);
+        ram.0[address_space::RES_VECTOR as usize] = 0x00;
+        ram.0[address_space::RES_VECTOR as usize + 1] = 0x10;
+
+        let mut cpu = MOS6502::new(ram);
+        cpu.reset();
+        cpu
+    }
+
+    #[test]
+    fn rdy_halts_operand_read() {
+        // LDA $2000
+        let mut cpu = cpu(&[0xAD, 0x00, 0x20]);
+        cpu.get_bus().0[0x2000] = 0x42;
+
+        cpu.cycle(true);
+
+        for _ in 0..10 {
+            cpu.cycle(false);
+            assert_eq!((cpu.pc, cpu.cycle), (0x1001, 2));
+        }
+
+        cpu.cycle(true);
+        cpu.cycle(true);
+        cpu.cycle(false);
+        assert_eq!(cpu.a, 0);
+
+        cpu.cycle(true);
+        assert_eq!((cpu.a, cpu.pc, cpu.cycle), (0x42, 0x1003, 1));
+    }
+
+    #[test]
+    fn rdy_does_not_halt_write() {
+        // LDA #$42, STA $2000
+        let mut cpu = cpu(&[0xA9, 0x42, 0x8D, 0x00, 0x20]);
+
+        for _ in 0..5 {
+            cpu.cycle(true);
+        }
+
+        assert_eq!(cpu.bus().0[0x2000], 0xEA);
+
+        // the write goes through with RDY low, the next opcode fetch is then held
+        cpu.cycle(false);
+        assert_eq!(cpu.bus().0[0x2000], 0x42);
+        assert_eq!((cpu.pc, cpu.cycle), (0x1005, 1));
+
+        cpu.cycle(false);
+        assert_eq!((cpu.pc, cpu.cycle), (0x1005, 1));
+    }
+
+    #[test]
+    fn last_cycle_matches_instructions() {
+        for opcode in (0..=255).filter(|opcode| try_decode(*opcode).is_some()) {
+            let mut longest = 0;
+
+            // every combination of flags, page crossing and branch direction
+            for sr in [0, 0xFF] {
+                for index in [0, 0xFF] {
+                    for operand in [0x00, 0x7F, 0x80] {
+                        let mut cpu = cpu(&[]);
+                        cpu.get_bus().0.fill(operand);
+                        cpu.get_bus().0[0x10F0] = opcode;
+                        (cpu.pc, cpu.sr, cpu.x, cpu.y, cpu.sp) = (0x10F0, sr, index, index, 0x80);
+
+                        loop {
+                            longest = longest.max(cpu.cycle);
+                            cpu.cycle(true);
+
+                            if cpu.cycle == 1 {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+
+            assert_eq!(longest, last_cycle(decode(opcode)), "opcode {:02X}", opcode);
+        }
+    }
+}
@@ -21,11 +21,13 @@ pub trait AddressBus {
     fn write(&mut self, addr: u16, value: u8);
 }
 
+#[derive(Clone, Copy)]
 pub struct Instruction {
     pub name: Mnemonic,
     pub mode: AddressMode
 }
 
+#[derive(Clone, Copy)]
 pub enum AddressMode {
     Special,
     Implied,
@@ -3,8 +3,18 @@ mod cpu_6502_cpu;
 mod palette;
 mod decode;
 mod sound;
+mod tv_standard;
+mod power_on;
+mod system;
+mod state;
+mod rewind;
+mod movie;
+mod png;
+mod capture;
 
+pub mod controller;
 pub mod riot;
+pub mod savekey;
 pub mod tia;
 pub mod atari2600;
 
@@ -13,5 +23,13 @@ pub use {
     cpu_6502_cpu::*,
     palette::*,
     decode::decode,
-    sound::*
+    sound::*,
+    tv_standard::TvStandard,
+    power_on::PowerOnState,
+    system::{System, FrameView},
+    state::{fnv1a, StateError},
+    rewind::Rewind,
+    movie::{Movie, MovieFrame, MovieStart, Player, Playback, Recorder},
+    png::encode_png,
+    capture::Capture
 };
\ No newline at end of file
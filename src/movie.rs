@@ -0,0 +1,303 @@
+use crate::{controller::{Controller, ControllerEvent, Port}, riot::ConsoleSwitches, state::{fnv1a, Field, StateError, StateReader, StateWriter}, PowerOnState, System};
+
+const MAGIC: [u8; 4] = *b"A26M";
+const VERSION: u16 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MovieStart {
+    PowerOn(PowerOnState),
+    State(Vec<u8>)
+}
+
+// the input applied before a frame and the hash of the machine state after it
+#[derive(Clone, Debug)]
+pub struct MovieFrame {
+    pub switches: ConsoleSwitches,
+    pub events: Vec<(Port, ControllerEvent)>,
+    pub hash: u64
+}
+
+// controllers are named the way the frontend names them, it builds them from those names for `Movie::start`
+#[derive(Clone, Debug)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub start: MovieStart,
+    pub controllers: [String; 2],
+    pub frames: Vec<MovieFrame>
+}
+
+impl Movie {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::with_magic(MAGIC, VERSION);
+        writer.put(&self.rom_hash);
+        writer.put(&self.start);
+        writer.put(&self.controllers[0]);
+        writer.put(&self.controllers[1]);
+        writer.put(&self.frames);
+        writer.finish()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, StateError> {
+        let mut reader = StateReader::with_magic(data, MAGIC, VERSION)?;
+
+        let movie = Movie {
+            rom_hash: reader.get()?,
+            start: reader.get()?,
+            controllers: [reader.get()?, reader.get()?],
+            frames: reader.get()?
+        };
+
+        reader.finish()?;
+        Ok(movie)
+    }
+
+    // a system in the state the movie was recorded from. `controller` builds a controller from one of the names in
+    // `controllers`, both are connected before the start state is loaded since it holds their state too
+    pub fn start(&self, rom: Vec<u8>, controller: impl Fn(&str) -> Box<dyn Controller>) -> Result<System, StateError> {
+        if fnv1a(&rom) != self.rom_hash {
+            return Err(StateError::RomMismatch);
+        }
+
+        let mut system = match &self.start {
+            MovieStart::PowerOn(power_on) => System::with_power_on(rom, *power_on),
+            MovieStart::State(_) => System::new(rom)
+        };
+
+        system.connect(Port::Left, controller(&self.controllers[Port::Left as usize]));
+        system.connect(Port::Right, controller(&self.controllers[Port::Right as usize]));
+
+        if let MovieStart::State(state) = &self.start {
+            system.load_state(state)?;
+        }
+
+        Ok(system)
+    }
+}
+
+// the frontend passes along every input it gives the system and calls `frame` after each frame
+pub struct Recorder {
+    movie: Movie,
+    events: Vec<(Port, ControllerEvent)>
+}
+
+impl Recorder {
+    // `system` should be the one `start` describes, freshly powered on or holding that state
+    pub fn new(system: &System, start: MovieStart, controllers: [String; 2]) -> Self {
+        Recorder {
+            movie: Movie { rom_hash: system.rom_hash(), start, controllers, frames: Vec::new() },
+            events: Vec::new()
+        }
+    }
+
+    pub fn event(&mut self, port: Port, event: ControllerEvent) {
+        self.events.push((port, event));
+    }
+
+    pub fn frame(&mut self, system: &System) {
+        self.movie.frames.push(MovieFrame {
+            switches: system.switches(),
+            events: std::mem::take(&mut self.events),
+            hash: fnv1a(&system.save_state())
+        });
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Playback {
+    Playing,
+    // only reported for the first frame whose state doesn't match the recording
+    Diverged(u32),
+    Finished
+}
+
+pub struct Player {
+    movie: Movie,
+    frame: usize,
+    divergence: Option<u32>
+}
+
+impl Player {
+    pub fn new(movie: Movie) -> Self {
+        Player { movie, frame: 0, divergence: None }
+    }
+
+    pub fn run_frame(&mut self, system: &mut System) -> Playback {
+        let Some(frame) = self.movie.frames.get(self.frame) else {
+            return Playback::Finished;
+        };
+
+        system.set_switches(frame.switches);
+
+        for (port, event) in &frame.events {
+            system.controller_event(*port, *event);
+        }
+
+        system.run_frame();
+        self.frame += 1;
+
+        if self.divergence.is_none() && fnv1a(&system.save_state()) != frame.hash {
+            self.divergence = Some(self.frame as u32 - 1);
+            return Playback::Diverged(self.frame as u32 - 1);
+        }
+
+        Playback::Playing
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn divergence(&self) -> Option<u32> {
+        self.divergence
+    }
+}
+
+impl Field for MovieStart {
+    fn save(&self, writer: &mut StateWriter) {
+        match self {
+            MovieStart::PowerOn(PowerOnState::Fixed) => writer.put(&0u8),
+            MovieStart::PowerOn(PowerOnState::Random(seed)) => {
+                writer.put(&1u8);
+                writer.put(seed);
+            }
+            MovieStart::State(state) => {
+                writer.put(&2u8);
+                writer.put(state);
+            }
+        }
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        match reader.get::<u8>()? {
+            0 => Ok(MovieStart::PowerOn(PowerOnState::Fixed)),
+            1 => Ok(MovieStart::PowerOn(PowerOnState::Random(reader.get()?))),
+            2 => Ok(MovieStart::State(reader.get()?)),
+            _ => Err(StateError::Invalid)
+        }
+    }
+}
+
+impl Field for MovieFrame {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.put(&self.switches);
+        writer.put(&self.events);
+        writer.put(&self.hash);
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        Ok(MovieFrame { switches: reader.get()?, events: reader.get()?, hash: reader.get()? })
+    }
+}
+
+impl Field for Port {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.put(&(*self as u8));
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        match reader.get::<u8>()? {
+            0 => Ok(Port::Left),
+            1 => Ok(Port::Right),
+            _ => Err(StateError::Invalid)
+        }
+    }
+}
+
+impl Field for ControllerEvent {
+    fn save(&self, writer: &mut StateWriter) {
+        match *self {
+            ControllerEvent::Up(pressed) => { writer.put(&0u8); writer.put(&pressed); }
+            ControllerEvent::Down(pressed) => { writer.put(&1u8); writer.put(&pressed); }
+            ControllerEvent::Left(pressed) => { writer.put(&2u8); writer.put(&pressed); }
+            ControllerEvent::Right(pressed) => { writer.put(&3u8); writer.put(&pressed); }
+            ControllerEvent::Fire(pressed) => { writer.put(&4u8); writer.put(&pressed); }
+            ControllerEvent::Button(index, pressed) => { writer.put(&5u8); writer.put(&(index as u8)); writer.put(&pressed); }
+            ControllerEvent::Paddle(index, position) => { writer.put(&6u8); writer.put(&(index as u8)); writer.put(&position); }
+            ControllerEvent::Key(index, pressed) => { writer.put(&7u8); writer.put(&(index as u8)); writer.put(&pressed); }
+            ControllerEvent::Motion(dx, dy) => { writer.put(&8u8); writer.put(&dx); writer.put(&dy); }
+            ControllerEvent::Aim(x, y) => { writer.put(&9u8); writer.put(&x); writer.put(&y); }
+        }
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        Ok(match reader.get::<u8>()? {
+            0 => ControllerEvent::Up(reader.get()?),
+            1 => ControllerEvent::Down(reader.get()?),
+            2 => ControllerEvent::Left(reader.get()?),
+            3 => ControllerEvent::Right(reader.get()?),
+            4 => ControllerEvent::Fire(reader.get()?),
+            5 => ControllerEvent::Button(reader.get::<u8>()? as usize, reader.get()?),
+            6 => ControllerEvent::Paddle(reader.get::<u8>()? as usize, reader.get()?),
+            7 => ControllerEvent::Key(reader.get::<u8>()? as usize, reader.get()?),
+            8 => ControllerEvent::Motion(reader.get()?, reader.get()?),
+            9 => ControllerEvent::Aim(reader.get()?, reader.get()?),
+            _ => return Err(StateError::Invalid)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::{Joystick, Keypad, Paddles};
+
+    // LDA SWCHA, STA $80, JMP $F000
+    fn rom() -> Vec<u8> {
+        System::test_rom(&[0xAD, 0x80, 0x02, 0x85, 0x80, 0x4C, 0x00, 0xF0])
+    }
+
+    fn controller(name: &str) -> Box<dyn Controller> {
+        match name {
+            "paddles" => Box::new(Paddles::new()),
+            "keypad" => Box::new(Keypad::new()),
+            _ => Box::new(Joystick::new())
+        }
+    }
+
+    // runs `frames` frames recorded from a state and plays them back
+    fn record_and_play(system: &mut System, controllers: [&str; 2], frames: usize) -> Vec<Playback> {
+        let mut recorder = Recorder::new(system, MovieStart::State(system.save_state()), controllers.map(String::from));
+
+        for _ in 0..frames {
+            system.run_frame();
+            recorder.frame(system);
+        }
+
+        let movie = recorder.finish();
+        let mut system = movie.start(rom(), controller).unwrap();
+        let mut player = Player::new(movie);
+
+        (0..=frames).map(|_| player.run_frame(&mut system)).collect()
+    }
+
+    #[test]
+    fn movie_from_state_keeps_held_input() {
+        let mut system = System::new(rom());
+        system.controller_event(Port::Left, ControllerEvent::Right(true));
+        system.run_frame();
+
+        assert_eq!(record_and_play(&mut system, ["joystick", "joystick"], 3), [Playback::Playing, Playback::Playing, Playback::Playing, Playback::Finished]);
+        assert_eq!(system.atari().riot.read(0x80) & 0xF0, 0x70);
+    }
+
+    #[test]
+    fn movie_from_state_with_other_controllers() {
+        let mut system = System::new(rom());
+        system.connect(Port::Left, controller("paddles"));
+        system.connect(Port::Right, controller("keypad"));
+        system.controller_event(Port::Left, ControllerEvent::Paddle(0, 40));
+        system.controller_event(Port::Left, ControllerEvent::Button(0, true));
+        system.controller_event(Port::Right, ControllerEvent::Key(4, true));
+        system.run_frame();
+
+        assert_eq!(record_and_play(&mut system, ["paddles", "keypad"], 3), [Playback::Playing, Playback::Playing, Playback::Playing, Playback::Finished]);
+    }
+}
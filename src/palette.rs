@@ -130,4 +130,151 @@ pub fn palette_rgb(color: u8) -> (u8, u8, u8) {
         0xFE => (254, 223, 112),
         _ => panic!()
     }
+}
+pub fn palette_rgb_pal(color: u8) -> (u8, u8, u8) {
+    match color & !0x01 {
+        0x00 => (0, 0, 0),
+        0x02 => (43, 43, 43),
+        0x04 => (82, 82, 82),
+        0x06 => (118, 118, 118),
+        0x08 => (151, 151, 151),
+        0x0A => (182, 182, 182),
+        0x0C => (210, 210, 210),
+        0x0E => (236, 236, 236),
+        0x10 => (0, 0, 0),
+        0x12 => (43, 43, 43),
+        0x14 => (82, 82, 82),
+        0x16 => (118, 118, 118),
+        0x18 => (151, 151, 151),
+        0x1A => (182, 182, 182),
+        0x1C => (210, 210, 210),
+        0x1E => (236, 236, 236),
+        0x20 => (128, 88, 0),
+        0x22 => (154, 114, 26),
+        0x24 => (178, 138, 50),
+        0x26 => (200, 160, 72),
+        0x28 => (220, 180, 92),
+        0x2A => (239, 199, 111),
+        0x2C => (255, 216, 128),
+        0x2E => (255, 232, 144),
+        0x30 => (68, 92, 0),
+        0x32 => (94, 118, 26),
+        0x34 => (118, 142, 50),
+        0x36 => (140, 164, 72),
+        0x38 => (160, 184, 92),
+        0x3A => (179, 203, 111),
+        0x3C => (196, 220, 128),
+        0x3E => (212, 236, 144),
+        0x40 => (112, 52, 0),
+        0x42 => (138, 78, 26),
+        0x44 => (162, 102, 50),
+        0x46 => (184, 124, 72),
+        0x48 => (204, 144, 92),
+        0x4A => (223, 163, 111),
+        0x4C => (240, 180, 128),
+        0x4E => (255, 196, 144),
+        0x50 => (0, 100, 20),
+        0x52 => (26, 126, 46),
+        0x54 => (50, 150, 70),
+        0x56 => (72, 172, 92),
+        0x58 => (92, 192, 112),
+        0x5A => (111, 211, 131),
+        0x5C => (128, 228, 148),
+        0x5E => (144, 244, 164),
+        0x60 => (112, 0, 20),
+        0x62 => (138, 26, 46),
+        0x64 => (162, 50, 70),
+        0x66 => (184, 72, 92),
+        0x68 => (204, 92, 112),
+        0x6A => (223, 111, 131),
+        0x6C => (240, 128, 148),
+        0x6E => (255, 144, 164),
+        0x70 => (0, 92, 92),
+        0x72 => (26, 118, 118),
+        0x74 => (50, 142, 142),
+        0x76 => (72, 164, 164),
+        0x78 => (92, 184, 184),
+        0x7A => (111, 203, 203),
+        0x7C => (128, 220, 220),
+        0x7E => (144, 236, 236),
+        0x80 => (112, 0, 92),
+        0x82 => (138, 26, 118),
+        0x84 => (162, 50, 142),
+        0x86 => (184, 72, 164),
+        0x88 => (204, 92, 184),
+        0x8A => (223, 111, 203),
+        0x8C => (240, 128, 220),
+        0x8E => (255, 144, 236),
+        0x90 => (0, 60, 112),
+        0x92 => (26, 86, 138),
+        0x94 => (50, 110, 162),
+        0x96 => (72, 132, 184),
+        0x98 => (92, 152, 204),
+        0x9A => (111, 171, 223),
+        0x9C => (128, 188, 240),
+        0x9E => (144, 204, 255),
+        0xA0 => (88, 0, 112),
+        0xA2 => (114, 26, 138),
+        0xA4 => (138, 50, 162),
+        0xA6 => (160, 72, 184),
+        0xA8 => (180, 92, 204),
+        0xAA => (199, 111, 223),
+        0xAC => (216, 128, 240),
+        0xAE => (232, 144, 255),
+        0xB0 => (0, 32, 112),
+        0xB2 => (26, 58, 138),
+        0xB4 => (50, 82, 162),
+        0xB6 => (72, 104, 184),
+        0xB8 => (92, 124, 204),
+        0xBA => (111, 143, 223),
+        0xBC => (128, 160, 240),
+        0xBE => (144, 176, 255),
+        0xC0 => (60, 0, 128),
+        0xC2 => (86, 26, 154),
+        0xC4 => (110, 50, 178),
+        0xC6 => (132, 72, 200),
+        0xC8 => (152, 92, 220),
+        0xCA => (171, 111, 239),
+        0xCC => (188, 128, 255),
+        0xCE => (204, 144, 255),
+        0xD0 => (0, 0, 136),
+        0xD2 => (26, 26, 162),
+        0xD4 => (50, 50, 186),
+        0xD6 => (72, 72, 208),
+        0xD8 => (92, 92, 228),
+        0xDA => (111, 111, 247),
+        0xDC => (128, 128, 255),
+        0xDE => (144, 144, 255),
+        0xE0 => (0, 0, 0),
+        0xE2 => (43, 43, 43),
+        0xE4 => (82, 82, 82),
+        0xE6 => (118, 118, 118),
+        0xE8 => (151, 151, 151),
+        0xEA => (182, 182, 182),
+        0xEC => (210, 210, 210),
+        0xEE => (236, 236, 236),
+        0xF0 => (0, 0, 0),
+        0xF2 => (43, 43, 43),
+        0xF4 => (82, 82, 82),
+        0xF6 => (118, 118, 118),
+        0xF8 => (151, 151, 151),
+        0xFA => (182, 182, 182),
+        0xFC => (210, 210, 210),
+        0xFE => (236, 236, 236),
+        _ => panic!()
+    }
+}
+
+pub fn palette_rgb_secam(color: u8) -> (u8, u8, u8) {
+    match color & 0x0E {
+        0x00 => (0, 0, 0),
+        0x02 => (33, 33, 255),
+        0x04 => (240, 60, 121),
+        0x06 => (255, 80, 255),
+        0x08 => (127, 255, 0),
+        0x0A => (127, 255, 255),
+        0x0C => (255, 255, 63),
+        0x0E => (255, 255, 255),
+        _ => panic!()
+    }
 }
\ No newline at end of file
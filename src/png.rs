@@ -0,0 +1,132 @@
+// a minimal PNG encoder: 8-bit RGB, no filtering and zlib stored blocks, which is plenty for screenshots
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+
+        while k < 8 {
+            c = if c & 1 != 0 { 0xEDB8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+
+        table[n] = c;
+        n += 1;
+    }
+
+    table
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, byte| CRC_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1u32, 0u32), |(a, b), byte| {
+        let a = (a + *byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+
+    b << 16 | a
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        out.push(blocks.peek().is_none() as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+// `rgb` holds `width * height` pixels, three bytes each
+pub fn encode_png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    assert_eq!(rgb.len(), width * height * 3, "pixel data doesn't match the image size");
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    // every row starts with filter type 0
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+
+    for row in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut out = SIGNATURE.to_vec();
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiny_image() {
+        let png = encode_png(2, 1, &[0xFF, 0, 0, 0, 0, 0xFF]);
+
+        assert_eq!(png[..8], SIGNATURE);
+
+        // width 2, height 1, 8-bit RGB, no interlacing
+        assert_eq!(png[8..16], [0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(png[16..29], [0, 0, 0, 2, 0, 0, 0, 1, 8, 2, 0, 0, 0]);
+        assert_eq!(png[29..33], 0x7B40_E8DDu32.to_be_bytes());
+
+        // one final stored block holding the filter byte and both pixels, then the Adler-32
+        assert_eq!(png[33..41], [0, 0, 0, 18, b'I', b'D', b'A', b'T']);
+        assert_eq!(png[41..48], [0x78, 0x01, 1, 7, 0, 0xF8, 0xFF]);
+        assert_eq!(png[48..55], [0, 0xFF, 0, 0, 0, 0, 0xFF]);
+        assert_eq!(png[55..59], 0x0700_01FFu32.to_be_bytes());
+        assert_eq!(png[59..63], 0x5536_BAC7u32.to_be_bytes());
+
+        assert_eq!(png[63..], [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+    }
+
+    #[test]
+    fn stored_blocks_split_at_64k() {
+        let data = vec![0x55; MAX_STORED_BLOCK + 6];
+        let zlib = zlib_stored(&data);
+
+        assert_eq!(zlib[2..7], [0, 0xFF, 0xFF, 0, 0]);
+        let second = 7 + MAX_STORED_BLOCK;
+        assert_eq!(zlib[second..second + 5], [1, 6, 0, 0xF9, 0xFF]);
+        assert_eq!(zlib.len(), second + 5 + 6 + 4);
+
+        assert_eq!(zlib_stored(&[]), [0x78, 0x01, 1, 0, 0, 0xFF, 0xFF, 0, 0, 0, 1]);
+    }
+}
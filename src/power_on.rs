@@ -0,0 +1,89 @@
+use crate::{atari2600::Atari2600, MOS6502};
+
+// what RAM, registers and TIA positions hold at power-on, `Fixed` keeps runs deterministic
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum PowerOnState {
+    #[default]
+    Fixed,
+    Random(u64)
+}
+
+impl PowerOnState {
+    // call before `MOS6502::reset`
+    pub fn apply(self, cpu: &mut MOS6502<Atari2600>) {
+        if let PowerOnState::Random(seed) = self {
+            let mut rng = Rng::new(seed);
+            cpu.randomize(&mut rng);
+
+            let atari = cpu.get_bus();
+            atari.riot.randomize(&mut rng);
+            atari.tia.randomize(&mut rng);
+        }
+    }
+}
+
+// splitmix64, the same seed always gives the same power-on state
+pub(crate) struct Rng {
+    state: u64
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    // in start..end
+    pub fn range(&mut self, start: u16, end: u16) -> u16 {
+        start + (self.next_u64() % (end - start) as u64) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{state::StateWriter, System};
+
+    // the CPU and console right after reset, `None` skips the power-on step entirely
+    fn state(power_on: Option<PowerOnState>) -> Vec<u8> {
+        let mut cpu = MOS6502::new(Atari2600::new(System::test_rom(&[])));
+
+        if let Some(power_on) = power_on {
+            power_on.apply(&mut cpu);
+        }
+
+        cpu.reset();
+
+        let mut writer = StateWriter::bare();
+        cpu.save_state(&mut writer);
+        cpu.bus().save_state(&mut writer);
+        writer.finish()
+    }
+
+    #[test]
+    fn fixed_is_the_baseline() {
+        assert!(state(Some(PowerOnState::Fixed)) == state(None));
+    }
+
+    #[test]
+    fn random_is_deterministic_per_seed() {
+        for seed in [0, 1, 0xDEAD_BEEF] {
+            let random = state(Some(PowerOnState::Random(seed)));
+
+            assert!(random == state(Some(PowerOnState::Random(seed))), "seed {:X}", seed);
+            assert!(random != state(None), "seed {:X}", seed);
+            assert!(random != state(Some(PowerOnState::Random(seed + 1))), "seed {:X}", seed);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+
+use crate::{StateError, System};
+
+// a state after every frame, without the frame buffer. The newest is kept whole, older ones as the
+// run-length encoded XOR against the state after them. States change length when controllers
+// connect or pins float, so each delta carries the length of the state it rebuilds
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>
+}
+
+impl Rewind {
+    // at most `capacity` frames
+    pub fn new(capacity: usize) -> Self {
+        Rewind { capacity: capacity.max(2), latest: None, deltas: VecDeque::new() }
+    }
+
+    // call after every frame
+    pub fn capture(&mut self, system: &System) {
+        let state = system.save_state_without_frame();
+
+        if let Some(latest) = self.latest.replace(state) {
+            let delta = xor(self.latest.as_ref().unwrap(), &latest);
+            self.deltas.push_back(compress(&delta));
+
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+    }
+
+    // goes back to the frame before the newest one, which is redrawn by running it again from the state before it.
+    // Returns false once there's nothing left to go back to
+    pub fn step_back(&mut self, system: &mut System) -> Result<bool, StateError> {
+        let (Some(latest), Some(before)) = (&self.latest, self.deltas.len().checked_sub(2).map(|index| &self.deltas[index])) else {
+            return Ok(false);
+        };
+
+        let previous = xor(latest, &decompress(self.deltas.back().unwrap()));
+        let redraw = xor(&previous, &decompress(before));
+
+        system.load_state(&redraw)?;
+        system.run_frame();
+        system.load_state(&previous)?;
+
+        self.deltas.pop_back();
+        self.latest = Some(previous);
+        Ok(true)
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+}
+
+// `b` XORed with `a`, as long as `b` and with `a` padded with zeros. Applying the result to `a` gives back `b`
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    b.iter().enumerate().map(|(index, b)| a.get(index).unwrap_or(&0) ^ b).collect()
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+fn get_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= (byte as usize & 0x7F) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return value;
+        }
+    }
+}
+
+// pairs of (zero run, literal run) with the literal bytes after them, unchanged bytes XOR to zero
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    put_varint(&mut out, data.len());
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let zeros = data[pos..].iter().take_while(|byte| **byte == 0).count();
+        pos += zeros;
+        let literals = data[pos..].iter().take_while(|byte| **byte != 0).count();
+
+        put_varint(&mut out, zeros);
+        put_varint(&mut out, literals);
+        out.extend_from_slice(&data[pos..pos + literals]);
+        pos += literals;
+    }
+
+    out
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = get_varint(data, &mut pos);
+    let mut out = Vec::with_capacity(len);
+
+    while out.len() < len {
+        let zeros = get_varint(data, &mut pos);
+        let literals = get_varint(data, &mut pos);
+
+        out.resize(out.len() + zeros, 0);
+        out.extend_from_slice(&data[pos..pos + literals]);
+        pos += literals;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::{ControllerEvent, Genesis, Port};
+
+    // a 262 line frame with the background set to a counter that goes up every frame
+    const PROGRAM: [u8; 37] = [
+        0xE6, 0x80, // INC $80
+        0xA5, 0x80, // LDA $80
+        0x85, 0x09, // STA COLUBK
+        0xA9, 0x02, // LDA #2
+        0x85, 0x00, // STA VSYNC
+        0x85, 0x02, 0x85, 0x02, 0x85, 0x02, // STA WSYNC x3
+        0xA9, 0x00, // LDA #0
+        0x85, 0x00, // STA VSYNC
+        0xA2, 200, 0x85, 0x02, 0xCA, 0xD0, 0xFB, // 200 lines
+        0xA2, 59, 0x85, 0x02, 0xCA, 0xD0, 0xFB, // 59 lines
+        0x4C, 0x00, 0xF0 // JMP $F000
+    ];
+
+    #[test]
+    fn steps_back_one_frame_at_a_time() {
+        let mut system = System::new(System::test_rom(&PROGRAM));
+        let mut rewind = Rewind::new(100);
+        let mut states = Vec::new();
+
+        for _ in 0..10 {
+            system.run_frame();
+            rewind.capture(&system);
+            states.push(system.save_state());
+        }
+
+        assert!(system.save_state_without_frame().len() < 1000);
+
+        let frame = system.frame().to_vec();
+        assert_eq!(rewind.step_back(&mut system), Ok(true));
+        assert!(system.frame() != frame.as_slice());
+        assert!(system.save_state() == states[8]);
+
+        // the first state is only there to redraw the second
+        for frame in (1..8).rev() {
+            assert_eq!(rewind.step_back(&mut system), Ok(true));
+            assert!(system.save_state() == states[frame], "frame {}", frame);
+        }
+
+        assert_eq!(rewind.step_back(&mut system), Ok(false));
+        assert!(system.save_state() == states[1]);
+    }
+
+    #[test]
+    fn states_of_different_lengths() {
+        let mut system = System::new(System::test_rom(&PROGRAM));
+        system.connect(Port::Left, Box::new(Genesis::new()));
+        let mut rewind = Rewind::new(100);
+        let mut states = Vec::new();
+
+        // button C floats or grounds a pot, which changes the length of the state
+        for frame in 0..10 {
+            system.controller_event(Port::Left, ControllerEvent::Button(0, frame % 2 == 1));
+            system.run_frame();
+            rewind.capture(&system);
+            states.push(system.save_state());
+        }
+
+        assert!(states[0].len() != states[1].len());
+
+        for frame in (1..9).rev() {
+            assert_eq!(rewind.step_back(&mut system), Ok(true));
+            assert!(system.save_state() == states[frame], "frame {}", frame);
+        }
+    }
+
+    #[test]
+    fn state_for_another_system_is_an_error() {
+        let mut rom = System::test_rom(&PROGRAM);
+        let mut system = System::new(rom.clone());
+        let mut rewind = Rewind::new(100);
+
+        for _ in 0..3 {
+            system.run_frame();
+            rewind.capture(&system);
+        }
+
+        rom[0x800] = 0;
+        let mut other = System::new(rom);
+        let state = other.save_state();
+        assert_eq!(rewind.step_back(&mut other), Err(StateError::RomMismatch));
+        assert!(other.save_state() == state);
+    }
+}
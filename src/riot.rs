@@ -1,31 +1,105 @@
+use crate::{power_on::Rng, state::{Field, StateError, StateReader, StateWriter}};
+
 const PA7_FLAG: u8 = 1 << 6;
 const TIMER_FLAG: u8 = 1 << 7;
+const TIMER_INTERVALS: [u16; 4] = [1, 8, 64, 1024];
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum PA7EdgeDetect {
     Positive,
     Negative
 }
 
-#[derive(Clone, Copy)]
-pub enum Player {
-    Zero,
-    One
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Difficulty {
+    A,
+    B
 }
-pub enum JoystickDirection {
-    Up,
-    Down,
-    Left,
-    Right
+
+impl Field for Difficulty {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.put(&(*self == Difficulty::A));
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        Ok(if reader.get()? { Difficulty::A } else { Difficulty::B })
+    }
 }
 
-impl JoystickDirection {
-    fn porta_bit(self) -> i32 {
+impl Difficulty {
+    pub fn toggled(self) -> Self {
         match self {
-            JoystickDirection::Up => 0,
-            JoystickDirection::Down => 1,
-            JoystickDirection::Left => 2,
-            JoystickDirection::Right => 3,
+            Difficulty::A => Difficulty::B,
+            Difficulty::B => Difficulty::A
+        }
+    }
+}
+
+// the console switches as read through SWCHB, `reset` and `select` are true while held down
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConsoleSwitches {
+    pub reset: bool,
+    pub select: bool,
+    pub color: bool,
+    pub difficulty: [Difficulty; 2],
+    pub pause: bool
+}
+
+impl ConsoleSwitches {
+    pub fn new() -> Self {
+        ConsoleSwitches { reset: false, select: false, color: true, difficulty: [Difficulty::B; 2], pause: false }
+    }
+
+    // the 7800 has no color switch, its pause button flips that bit instead
+    pub fn swchb(&self) -> u8 {
+        let mut value = 0b0011_0100;
+
+        if !self.reset {
+            value |= 1 << 0;
         }
+
+        if !self.select {
+            value |= 1 << 1;
+        }
+
+        if self.color != self.pause {
+            value |= 1 << 3;
+        }
+
+        for (player, difficulty) in self.difficulty.iter().enumerate() {
+            if *difficulty == Difficulty::A {
+                value |= 1 << (6 + player);
+            }
+        }
+
+        value
+    }
+}
+
+impl Field for ConsoleSwitches {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.put(&self.reset);
+        writer.put(&self.select);
+        writer.put(&self.color);
+        writer.put(&self.difficulty[0]);
+        writer.put(&self.difficulty[1]);
+        writer.put(&self.pause);
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        Ok(ConsoleSwitches {
+            reset: reader.get()?,
+            select: reader.get()?,
+            color: reader.get()?,
+            difficulty: [reader.get()?, reader.get()?],
+            pause: reader.get()?
+        })
+    }
+}
+
+impl Default for ConsoleSwitches {
+    fn default() -> Self {
+        ConsoleSwitches::new()
     }
 }
 
@@ -34,10 +108,16 @@ pub struct Riot {
     timer_cnt: u16,
     timer_value: u8,
     timer_interval: u16,
+    timer_wrapped: bool,
     timer_irq_enable: bool,
     interrupt_flag: u8,
+    pa7_irq_enable: bool,
+    pa7_detect: PA7EdgeDetect,
+    pa7: bool,
     porta: u8,
-    portb: u8,
+    porta_latch: u8,
+    switches: ConsoleSwitches,
+    portb_latch: u8,
     ddca: u8,
     ddcb: u8
 }
@@ -49,112 +129,180 @@ impl Riot {
             timer_cnt: 0,
             timer_value: 0,
             timer_interval: 1024,
+            timer_wrapped: false,
             timer_irq_enable: false,
             interrupt_flag: 0,
+            pa7_irq_enable: false,
+            pa7_detect: PA7EdgeDetect::Negative,
+            pa7: true,
             porta: 0xFF,
-            portb: 0,
+            porta_latch: 0,
+            switches: ConsoleSwitches::new(),
+            portb_latch: 0,
             ddca: 0,
             ddcb: 0
         }
     }
 
-    pub fn switch_color(&mut self, enabled: bool) {
-        if enabled {
-            self.portb |= 1 << 3;
-        } else {
-            self.portb &= !(1 << 3);
+    pub(crate) fn randomize(&mut self, rng: &mut Rng) {
+        for byte in self.ram.iter_mut() {
+            *byte = rng.next_u8();
         }
+
+        self.timer_value = rng.next_u8();
+        self.timer_interval = TIMER_INTERVALS[rng.range(0, 4) as usize];
+        self.timer_cnt = rng.range(0, self.timer_interval);
     }
 
-    pub fn switch_select(&mut self, enabled: bool) {
-        if enabled {
-            self.portb |= 1 << 1;
-        } else {
-            self.portb &= !(1 << 1);
-        }
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.ram);
+        writer.put(&self.timer_cnt);
+        writer.put(&self.timer_value);
+        writer.put(&self.timer_interval);
+        writer.put(&self.timer_wrapped);
+        writer.put(&self.timer_irq_enable);
+        writer.put(&self.interrupt_flag);
+        writer.put(&self.pa7_irq_enable);
+        writer.put(&(self.pa7_detect == PA7EdgeDetect::Positive));
+        writer.put(&self.pa7);
+        writer.put(&self.porta);
+        writer.put(&self.porta_latch);
+        writer.put(&self.switches);
+        writer.put(&self.portb_latch);
+        writer.put(&self.ddca);
+        writer.put(&self.ddcb);
     }
 
-    pub fn switch_reset(&mut self, enabled: bool) {
-        if enabled {
-            self.portb |= 1 << 0;
-        } else {
-            self.portb &= !(1 << 0);
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.ram = reader.get()?;
+        self.timer_cnt = reader.get()?;
+        self.timer_value = reader.get()?;
+        self.timer_interval = reader.get()?;
+        self.timer_wrapped = reader.get()?;
+        self.timer_irq_enable = reader.get()?;
+        self.interrupt_flag = reader.get()?;
+        self.pa7_irq_enable = reader.get()?;
+        self.pa7_detect = if reader.get()? { PA7EdgeDetect::Positive } else { PA7EdgeDetect::Negative };
+        self.pa7 = reader.get()?;
+        self.porta = reader.get()?;
+        self.porta_latch = reader.get()?;
+        self.switches = reader.get()?;
+        self.portb_latch = reader.get()?;
+        self.ddca = reader.get()?;
+        self.ddcb = reader.get()?;
+
+        if !TIMER_INTERVALS.contains(&self.timer_interval) {
+            return Err(StateError::Invalid);
         }
+
+        Ok(())
+    }
+
+    pub fn switches(&self) -> ConsoleSwitches {
+        self.switches
+    }
+
+    pub fn set_switches(&mut self, switches: ConsoleSwitches) {
+        self.switches = switches;
+    }
+
+    pub fn porta_input(&mut self, value: u8) {
+        self.porta = value;
+        self.detect_pa7();
+    }
+
+    fn porta_pins(&self) -> u8 {
+        (self.porta_latch & self.ddca) | (self.porta & !self.ddca)
+    }
+
+    // bits set as outputs read back the latch, which games use as spare storage on port B
+    fn portb_pins(&self) -> u8 {
+        (self.portb_latch & self.ddcb) | (self.switches.swchb() & !self.ddcb)
     }
 
-    pub fn switch_joystick(&mut self, player: Player, dir: JoystickDirection, value: bool) {
-        let offset = if let Player::One = player { 0 } else { 4 };
+    fn detect_pa7(&mut self) {
+        let pa7 = self.porta_pins() & (1 << 7) != 0;
 
-        if value {
-            self.porta &= !(1 << (dir.porta_bit() + offset));
-        } else {
-            self.porta |= 1 << (dir.porta_bit() + offset);
+        let edge = match self.pa7_detect {
+            PA7EdgeDetect::Positive => pa7 && !self.pa7,
+            PA7EdgeDetect::Negative => !pa7 && self.pa7
+        };
+
+        if edge {
+            self.interrupt_flag |= PA7_FLAG;
         }
+
+        self.pa7 = pa7;
+    }
+
+    // pins set as inputs are pulled high
+    pub fn porta_output(&self) -> u8 {
+        (self.porta_latch & self.ddca) | !self.ddca
     }
 
     pub fn irq(&self) -> bool {
-        self.timer_irq_enable && self.interrupt_flag & TIMER_FLAG != 0
+        (self.timer_irq_enable && self.interrupt_flag & TIMER_FLAG != 0)
+        || (self.pa7_irq_enable && self.interrupt_flag & PA7_FLAG != 0)
     }
 
+    // after an underflow the timer counts every cycle until INTIM is read, the prescaler keeps running underneath
     pub fn cycle(&mut self) {
-        if self.timer_cnt == self.timer_interval - 1 {
+        let tick = self.timer_cnt == 0;
+        self.timer_cnt = if tick { self.timer_interval - 1 } else { self.timer_cnt - 1 };
+        self.timer_wrapped = false;
+
+        if self.interrupt_flag & TIMER_FLAG != 0 {
+            self.timer_value = self.timer_value.wrapping_sub(1);
+        } else if tick {
             self.timer_value = self.timer_value.wrapping_sub(1);
 
             if self.timer_value == 0xFF {
                 self.interrupt_flag |= TIMER_FLAG;
-                self.timer_interval = 1;
+                self.timer_wrapped = true;
             }
-
-            self.timer_cnt = 0xFFFF;
         }
-
-        self.timer_cnt = self.timer_cnt.wrapping_add(1);
     }
 
     fn read_ram(&self, addr: u16) -> u8 {
         self.ram[addr as usize & 0x7F]
     }
 
+    // the 6532 only decodes A0-A4 and A9, everything else is a mirror
     pub fn read(&mut self, addr: u16) -> u8 {
         //println!("RIOT read: 0x{:04X}", addr);
 
         match addr & (1 << 9) {
             0 => self.read_ram(addr),
             _ => match addr & (1 << 2) {
-                0 => match addr & 0x1287 {
-                    0x0283 => self.ddcb, //data-direction control for I/O register B
-                    0x0282 => self.portb, //I/O register B
-                    0x0281 => self.ddca, //data-direction control for I/O register A
-                    0x0280 => self.porta, //I/O register A
-                    _ => panic!("Unknown RIOT read register: 0x{:04X}", addr)
+                0 => match addr & 0b11 {
+                    3 => self.ddcb, //data-direction control for I/O register B
+                    2 => self.portb_pins(), //I/O register B
+                    1 => self.ddca, //data-direction control for I/O register A
+                    _ => self.porta_pins() //I/O register A
                 }
                 _ => match addr & (1 << 0) {
-                    0 => match addr & 0x128d {
-                        0x028C => { //enable the timer interrupt and read the timer
-                            self.timer_irq_enable = true;
-                            self.interrupt_flag &= !TIMER_FLAG;
-                            self.timer_value
-                        }
-                        0x0284 => { //disable the timer interrupt and read the timer
-                            self.timer_irq_enable = false;
-                            self.interrupt_flag &= !TIMER_FLAG;
-                            self.timer_value
-                        }
-                        _ => panic!("Unknown RIOT read register: 0x{:04X}", addr)
-                    }
-                    _ => match addr & 0x1285 {
-                        0x0285 => { //read the interrupt flags
-                            let value = self.interrupt_flag;
-                            self.interrupt_flag &= !PA7_FLAG;
-                            value
-                        }
-                        _ => panic!("Unknown RIOT read register: 0x{:04X}", addr)
+                    0 => self.read_timer(addr & (1 << 3) != 0), //enable (A3 set) or disable the timer interrupt and read the timer
+                    _ => { //read the interrupt flags
+                        let value = self.interrupt_flag;
+                        self.interrupt_flag &= !PA7_FLAG;
+                        value
                     }
                 }
             }
         }
     }
 
+    // reading on the cycle the timer underflows leaves the flag set
+    fn read_timer(&mut self, enable: bool) -> u8 {
+        self.timer_irq_enable = enable;
+
+        if !self.timer_wrapped {
+            self.interrupt_flag &= !TIMER_FLAG;
+        }
+
+        self.timer_value
+    }
+
     fn write_ram(&mut self, addr: u16, value: u8) {
         self.ram[addr as usize & 0x7F] = value
     }
@@ -165,31 +313,29 @@ impl Riot {
         match addr & (1 << 9) {
             0 => self.write_ram(addr, value),
             _ => match addr & (1 << 2) {
-                0 => match addr & 0x1287 {
-                    0x0283 => (), //data-direction control for I/O register B
-                    0x0282 => (), //I/O register B
-                    0x0281 => (), //data-direction control for I/O register A
-                    0x0280 => (), //I/O register A
-                    _ => panic!("Unknown RIOT write register: 0x{:04X}", addr)
+                0 => match addr & 0b11 {
+                    3 => self.ddcb = value, //data-direction control for I/O register B
+                    2 => self.portb_latch = value, //I/O register B
+                    1 => { //data-direction control for I/O register A
+                        self.ddca = value;
+                        self.detect_pa7();
+                    }
+                    _ => { //I/O register A
+                        self.porta_latch = value;
+                        self.detect_pa7();
+                    }
                 }
                 _ => match addr & (1 << 4) {
-                    0 => match addr & 0x1297 {
-                        //0x0287 => self.pa7_cfg(true, PA7EdgeDetect::Positive), //enable the PA7 interrupt and select positive edge-detect
-                        //0x0286 => self.pa7_cfg(true, PA7EdgeDetect::Negative), //enable the PA7 interrupt and select negative edge-detect
-                        0x0285 => self.pa7_cfg(false, PA7EdgeDetect::Positive), //disable the PA7 interrupt and select positive edge-detect
-                        0x0284 => self.pa7_cfg(false, PA7EdgeDetect::Negative), //disable the PA7 interrupt and select negative edge-detect
-                        _ => panic!("Unknown RIOT write register: 0x{:04X}", addr)
+                    0 => match addr & 0b11 {
+                        3 => self.pa7_cfg(true, PA7EdgeDetect::Positive), //enable the PA7 interrupt and select positive edge-detect
+                        2 => self.pa7_cfg(true, PA7EdgeDetect::Negative), //enable the PA7 interrupt and select negative edge-detect
+                        1 => self.pa7_cfg(false, PA7EdgeDetect::Positive), //disable the PA7 interrupt and select positive edge-detect
+                        _ => self.pa7_cfg(false, PA7EdgeDetect::Negative) //disable the PA7 interrupt and select negative edge-detect
                     }
-                    _ => match addr & 0x129f {
-                        //0x029F => self.timer_cfg(true, value, 1024), //enable the timer interrupt and set the timer using the 1024-cycle interval
-                        //0x029E => self.timer_cfg(true, value, 64), //enable the timer interrupt and set the timer using the 64-cycle interval
-                        //0x029D => self.timer_cfg(true, value, 8), //enable the timer interrupt and set the timer using the 8-cycle interval
-                        //0x029C => self.timer_cfg(true, value, 1), //enable the timer interrupt and set the timer using the 1-cycle interval
-                        0x0297 => self.timer_cfg(false, value, 1024), //disable the timer interrupt and set the timer using the 1024-cycle interval
-                        0x0296 => self.timer_cfg(false, value, 64), //disable the timer interrupt and set the timer using the 64-cycle interval
-                        0x0295 => self.timer_cfg(false, value, 8), //disable the timer interrupt and set the timer using the 8-cycle interval
-                        0x0294 => self.timer_cfg(false, value, 1), //disable the timer interrupt and set the timer using the 1-cycle interval
-                        _ => panic!("Unknown RIOT write register: 0x{:04X}", addr)
+                    _ => {
+                        //A3 enables the timer interrupt, A0-A1 select the 1, 8, 64 or 1024-cycle interval
+                        let interval = TIMER_INTERVALS[addr as usize & 0b11];
+                        self.timer_cfg(addr & (1 << 3) != 0, value, interval)
                     }
                 }
             }
@@ -197,14 +343,240 @@ impl Riot {
     }
 
     fn pa7_cfg(&mut self, enable: bool, detect: PA7EdgeDetect) {
-        unimplemented!();
+        self.pa7_irq_enable = enable;
+        self.pa7_detect = detect;
     }
 
+    // the first decrement happens on the cycle after the write
     fn timer_cfg(&mut self, enable: bool, value: u8, interval: u16) {
         self.timer_irq_enable = enable;
         self.timer_value = value;
         self.timer_interval = interval;
         self.interrupt_flag &= !TIMER_FLAG;
+        self.timer_wrapped = false;
         self.timer_cnt = 0;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SWCHA: u16 = 0x280;
+    const SWACNT: u16 = 0x281;
+    const SWCHB: u16 = 0x282;
+    const SWBCNT: u16 = 0x283;
+    const INTIM: u16 = 0x284;
+    const TIMINT: u16 = 0x285;
+    const TIM1T: u16 = 0x294;
+
+    fn pa7_flag(riot: &mut Riot) -> bool {
+        riot.read(TIMINT) & PA7_FLAG != 0
+    }
+
+    #[test]
+    fn portb_outputs_read_back_the_latch() {
+        let mut riot = Riot::new();
+        riot.set_switches(ConsoleSwitches { reset: true, difficulty: [Difficulty::B, Difficulty::A], ..ConsoleSwitches::new() });
+        assert_eq!(riot.read(SWCHB), 0b1011_1110);
+
+        // the low nibble as outputs, the switches still show through the inputs
+        riot.write(SWBCNT, 0x0F);
+        riot.write(SWCHB, 0xA5);
+        assert_eq!(riot.read(SWBCNT), 0x0F);
+        assert_eq!(riot.read(SWCHB), 0b1011_0101);
+
+        riot.set_switches(ConsoleSwitches::new());
+        assert_eq!(riot.read(SWCHB), 0b0011_0101);
+
+        // back to inputs, the latch is kept but no longer read
+        riot.write(SWBCNT, 0x00);
+        assert_eq!(riot.read(SWCHB), 0b0011_1111);
+        riot.write(SWBCNT, 0xFF);
+        assert_eq!(riot.read(SWCHB), 0xA5);
+    }
+
+    #[test]
+    fn pa7_edges_from_input() {
+        let mut riot = Riot::new();
+
+        // positive edge-detect ignores a falling PA7
+        riot.write(0x285, 0);
+        riot.porta_input(0x00);
+        assert!(!pa7_flag(&mut riot));
+        riot.porta_input(0x80);
+        assert!(pa7_flag(&mut riot));
+        assert!(!pa7_flag(&mut riot));
+
+        // negative edge-detect ignores a rising one
+        riot.write(0x284, 0);
+        riot.porta_input(0x00);
+        assert!(pa7_flag(&mut riot));
+        riot.porta_input(0x80);
+        assert!(!pa7_flag(&mut riot));
+
+        // other pins don't matter
+        riot.porta_input(0xFF);
+        riot.porta_input(0x80);
+        assert!(!pa7_flag(&mut riot));
+    }
+
+    #[test]
+    fn pa7_edges_from_output() {
+        let mut riot = Riot::new();
+        riot.porta_input(0xFF);
+        riot.write(0x285, 0);
+
+        // PA7 as an output follows the latch, not the pin's input
+        riot.write(SWCHA, 0x00);
+        riot.write(SWACNT, 0x80);
+        assert!(!pa7_flag(&mut riot));
+        riot.write(SWCHA, 0x80);
+        assert!(pa7_flag(&mut riot));
+
+        // switching back to input reads the high pin, no edge from high to high
+        riot.write(SWACNT, 0x00);
+        assert!(!pa7_flag(&mut riot));
+
+        riot.write(0x284, 0);
+        riot.write(SWCHA, 0x00);
+        riot.write(SWACNT, 0x80);
+        assert!(pa7_flag(&mut riot));
+    }
+
+    #[test]
+    fn timint_read_clears_only_pa7_flag() {
+        let mut riot = Riot::new();
+        riot.write(TIM1T, 0);
+        riot.cycle();
+        riot.cycle();
+
+        riot.write(0x284, 0);
+        riot.porta_input(0x00);
+
+        assert_eq!(riot.read(TIMINT), TIMER_FLAG | PA7_FLAG);
+        assert_eq!(riot.read(TIMINT), TIMER_FLAG);
+        assert_eq!(riot.read(TIMINT), TIMER_FLAG);
+    }
+
+    #[test]
+    fn pa7_irq_enable() {
+        // (register, enables the interrupt, edge that sets the flag)
+        for (addr, enable, rising) in [(0x284, false, false), (0x285, false, true), (0x286, true, false), (0x287, true, true)] {
+            let mut riot = Riot::new();
+            riot.write(addr, 0);
+            riot.porta_input(if rising { 0x00 } else { 0x80 });
+            assert!(!riot.irq(), "{:03X}", addr);
+
+            riot.porta_input(if rising { 0x80 } else { 0x00 });
+            assert_eq!(riot.irq(), enable, "{:03X}", addr);
+            assert_eq!(riot.interrupt_flag & PA7_FLAG, PA7_FLAG, "{:03X}", addr);
+
+            riot.read(TIMINT);
+            assert!(!riot.irq(), "{:03X}", addr);
+        }
+
+        // the timer interrupt is enabled separately by A3 of INTIM or the timer write
+        let mut riot = Riot::new();
+        riot.write(TIM1T, 0);
+        riot.cycle();
+        assert!(!riot.irq());
+        riot.read(INTIM | 0x08);
+        assert!(riot.irq());
+    }
+
+    #[test]
+    fn timer_first_decrement_after_write() {
+        for (addr, interval) in [(0x294, 1), (0x295, 8), (0x296, 64), (0x297, 1024)] {
+            let mut riot = Riot::new();
+            riot.write(addr, 10);
+            assert_eq!(riot.read(INTIM), 10, "{:03X}", addr);
+
+            riot.cycle();
+            assert_eq!(riot.read(INTIM), 9, "{:03X}", addr);
+
+            for _ in 1..interval {
+                riot.cycle();
+            }
+
+            assert_eq!(riot.read(INTIM), 9, "{:03X}", addr);
+            riot.cycle();
+            assert_eq!(riot.read(INTIM), 8, "{:03X}", addr);
+        }
+    }
+
+    #[test]
+    fn timer_counts_every_cycle_after_underflow() {
+        let mut riot = Riot::new();
+        riot.write(0x296, 1);
+        riot.cycle();
+        assert_eq!(riot.read(INTIM), 0);
+
+        for _ in 0..64 {
+            riot.cycle();
+        }
+
+        assert_eq!(riot.timer_value, 0xFF);
+        assert_eq!(riot.interrupt_flag, TIMER_FLAG);
+
+        riot.cycle();
+        assert_eq!(riot.timer_value, 0xFE);
+        riot.cycle();
+        assert_eq!(riot.timer_value, 0xFD);
+
+        // reading INTIM goes back to the 64-cycle interval
+        assert_eq!(riot.read(INTIM), 0xFD);
+        assert_eq!(riot.interrupt_flag, 0);
+
+        for _ in 0..60 {
+            riot.cycle();
+            assert_eq!(riot.timer_value, 0xFD);
+        }
+    }
+
+    #[test]
+    fn intim_read_on_underflow_cycle_keeps_flag() {
+        let mut riot = Riot::new();
+        riot.write(TIM1T, 0);
+        riot.cycle();
+        assert_eq!(riot.read(INTIM), 0xFF);
+        assert_eq!(riot.read(TIMINT) & TIMER_FLAG, TIMER_FLAG);
+
+        // a cycle later the read clears it
+        riot.cycle();
+        assert_eq!(riot.read(INTIM), 0xFE);
+        assert_eq!(riot.read(TIMINT) & TIMER_FLAG, 0);
+    }
+
+    #[test]
+    fn timer_mirrors() {
+        // A3 enables the interrupt, A0-A1 the interval, A4 selects the timer over the PA7 registers
+        for (addr, enable, interval) in [(0x294, false, 1), (0x29C, true, 1), (0x29D, true, 8), (0x29E, true, 64), (0x29F, true, 1024), (0x2D4, false, 1), (0x3FF, true, 1024)] {
+            let mut riot = Riot::new();
+            riot.write(addr, 5);
+            assert_eq!((riot.timer_irq_enable, riot.timer_interval, riot.timer_value), (enable, interval, 5), "{:03X}", addr);
+        }
+
+        // INTIM reads anywhere with A2 set and A0 clear, A3 enabling the interrupt
+        for (addr, enable) in [(0x284, false), (0x28C, true), (0x294, false), (0x29C, true), (0x2A4, false), (0x38C, true)] {
+            let mut riot = Riot::new();
+            riot.write(TIM1T, 5);
+            assert_eq!(riot.read(addr), 5, "{:03X}", addr);
+            assert_eq!(riot.timer_irq_enable, enable, "{:03X}", addr);
+        }
+
+        // the flags read with A0 set
+        for addr in [0x285, 0x287, 0x28D, 0x295, 0x29F] {
+            let mut riot = Riot::new();
+            riot.write(TIM1T, 0);
+            riot.cycle();
+            riot.cycle();
+            assert_eq!(riot.read(addr), TIMER_FLAG, "{:03X}", addr);
+        }
+
+        // RAM ignores A9 clear mirrors like $180
+        let mut riot = Riot::new();
+        riot.write(0x80, 0x42);
+        assert_eq!(riot.read(0x180), 0x42);
+    }
+}
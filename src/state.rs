@@ -0,0 +1,227 @@
+use std::fmt;
+
+const MAGIC: [u8; 4] = *b"A26S";
+
+// bump whenever a module adds, removes or reorders a field
+const VERSION: u16 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    RomMismatch,
+    Truncated,
+    Invalid
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "unrecognized file format"),
+            StateError::UnsupportedVersion(version) => write!(f, "unsupported file version {}", version),
+            StateError::RomMismatch => write!(f, "made with a different ROM"),
+            StateError::Truncated => write!(f, "file is truncated"),
+            StateError::Invalid => write!(f, "file is corrupt")
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+// 64-bit FNV-1a
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01B3))
+}
+
+pub(crate) struct StateWriter {
+    data: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter::with_magic(MAGIC, VERSION)
+    }
+
+    // other file formats share the encoding behind their own magic and version
+    pub fn with_magic(magic: [u8; 4], version: u16) -> Self {
+        let mut writer = StateWriter { data: Vec::new() };
+        writer.data.extend_from_slice(&magic);
+        writer.put(&version);
+        writer
+    }
+
+    // no header, for a part of a state that is stored as a field of another
+    pub fn bare() -> Self {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn put<T: Field>(&mut self, value: &T) {
+        value.save(self);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        StateReader::with_magic(data, MAGIC, VERSION)
+    }
+
+    pub fn with_magic(data: &'a [u8], magic: [u8; 4], version: u16) -> Result<Self, StateError> {
+        let mut reader = StateReader { data };
+
+        if reader.bytes(magic.len())? != magic {
+            return Err(StateError::BadMagic);
+        }
+
+        match reader.get()? {
+            v if v == version => Ok(reader),
+            v => Err(StateError::UnsupportedVersion(v))
+        }
+    }
+
+    pub fn bare(data: &'a [u8]) -> Self {
+        StateReader { data }
+    }
+
+    pub fn get<T: Field>(&mut self) -> Result<T, StateError> {
+        T::load(self)
+    }
+
+    pub fn finish(self) -> Result<(), StateError> {
+        match self.data.is_empty() {
+            true => Ok(()),
+            false => Err(StateError::Invalid)
+        }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() < len {
+            return Err(StateError::Truncated);
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+}
+
+// a value that can be written to and read back from a save state
+pub(crate) trait Field: Sized {
+    fn save(&self, writer: &mut StateWriter);
+    fn load(reader: &mut StateReader) -> Result<Self, StateError>;
+}
+
+macro_rules! int_field {
+    ($($t:ty),*) => {
+        $(impl Field for $t {
+            fn save(&self, writer: &mut StateWriter) {
+                writer.bytes(&self.to_le_bytes());
+            }
+
+            fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+                let bytes = reader.bytes(std::mem::size_of::<$t>())?;
+                Ok(<$t>::from_le_bytes(bytes.try_into().unwrap()))
+            }
+        })*
+    };
+}
+
+int_field!(u8, u16, u32, u64, i32);
+
+impl Field for bool {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.put(&(*self as u8));
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        match reader.get::<u8>()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid)
+        }
+    }
+}
+
+impl<T: Field> Field for Option<T> {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.put(&self.is_some());
+
+        if let Some(value) = self {
+            writer.put(value);
+        }
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        match reader.get::<bool>()? {
+            true => Ok(Some(reader.get()?)),
+            false => Ok(None)
+        }
+    }
+}
+
+impl<A: Field, B: Field> Field for (A, B) {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.put(&self.0);
+        writer.put(&self.1);
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        Ok((reader.get()?, reader.get()?))
+    }
+}
+
+impl<T: Field> Field for Vec<T> {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.put(&(self.len() as u32));
+
+        for value in self {
+            writer.put(value);
+        }
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        let len = reader.get::<u32>()? as usize;
+        (0..len).map(|_| reader.get()).collect()
+    }
+}
+
+impl Field for String {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.put(&(self.len() as u32));
+        writer.bytes(self.as_bytes());
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        let len = reader.get::<u32>()? as usize;
+        String::from_utf8(reader.bytes(len)?.to_vec()).map_err(|_| StateError::Invalid)
+    }
+}
+
+impl<T: Field + Copy + Default, const N: usize> Field for [T; N] {
+    fn save(&self, writer: &mut StateWriter) {
+        for value in self {
+            writer.put(value);
+        }
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        let mut array = [T::default(); N];
+
+        for value in array.iter_mut() {
+            *value = reader.get()?;
+        }
+
+        Ok(array)
+    }
+}
@@ -0,0 +1,350 @@
+use crate::{atari2600::Atari2600, png::encode_png, state::{fnv1a, StateError, StateReader, StateWriter}, controller::{Controller, ControllerEvent, Port}, riot::ConsoleSwitches, tia::{VisibleWindow, CLOCKS_PER_SCANLINE}, AudioConverter, PowerOnState, TvStandard, MOS6502};
+
+const HBLANK: u16 = 68;
+
+// color clocks per output sample, close enough to 44.1kHz for an audio device to resample
+const AUDIO_CLOCKS_PER_SAMPLE: u16 = 85;
+
+// which part of the frame to convert to RGB
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct FrameView {
+    // all 228 color clocks including HBLANK instead of the 160 visible pixels
+    pub hblank: bool,
+    // each pixel twice as wide, closer to the shape they have on a TV
+    pub double_width: bool
+}
+
+// the CPU and the console it's plugged into, clocked the same way as the real hardware
+pub struct System {
+    cpu: MOS6502<Atari2600>,
+    audio_converter: AudioConverter
+}
+
+impl System {
+    pub fn new(rom: Vec<u8>) -> Self {
+        System::with_power_on(rom, PowerOnState::Fixed)
+    }
+
+    pub fn with_power_on(rom: Vec<u8>, power_on: PowerOnState) -> Self {
+        let mut cpu = MOS6502::new(Atari2600::new(rom));
+        power_on.apply(&mut cpu);
+        cpu.reset();
+
+        System { cpu, audio_converter: AudioConverter::new(AUDIO_CLOCKS_PER_SAMPLE) }
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        fnv1a(&self.cpu.bus().rom)
+    }
+
+    pub fn atari(&mut self) -> &mut Atari2600 {
+        self.cpu.get_bus()
+    }
+
+    // one CPU cycle, returns true when it finished a frame. The 6502 samples RDY as the cycle begins, before the TIA
+    // clocks under it: WSYNC is released after color clock 227, so the first cycle to run again covers clocks 0-2
+    // and a WSYNC written on the last cycle of a line holds RDY through the next one (see the tests below)
+    pub fn cycle(&mut self) -> bool {
+        let atari = self.cpu.get_bus();
+        let rdy = !atari.tia.wsync();
+
+        for _ in 0..3 {
+            atari.tia.cycle();
+        }
+
+        atari.riot.cycle();
+
+        let drew = atari.tia.draw();
+
+        if drew {
+            atari.tia.drew();
+        }
+
+        self.cpu.cycle(rdy);
+        drew
+    }
+
+    // returns how many frames were finished
+    pub fn run_cycles(&mut self, cycles: u64) -> u32 {
+        let mut frames = 0;
+
+        for _ in 0..cycles {
+            frames += self.cycle() as u32;
+        }
+
+        frames
+    }
+
+    pub fn run_frame(&mut self) {
+        while !self.cycle() {}
+    }
+
+    // palette indices for every scanline, `CLOCKS_PER_SCANLINE` wide
+    pub fn frame(&self) -> &[u8] {
+        &self.cpu.bus().tia.frame
+    }
+
+    // the visible window in the palette of the TV standard, returns (width, height, rgb)
+    pub fn frame_rgb(&self, view: FrameView) -> (usize, usize, Vec<u8>) {
+        let window = self.visible_window();
+        let tv_standard = self.tv_standard();
+        let first = if view.hblank { 0 } else { HBLANK as usize };
+        let repeat = if view.double_width { 2 } else { 1 };
+        let width = (CLOCKS_PER_SCANLINE as usize - first) * repeat;
+        let mut rgb = Vec::with_capacity(width * window.height as usize * 3);
+
+        for row in self.frame().chunks(CLOCKS_PER_SCANLINE as usize).skip(window.ystart as usize).take(window.height as usize) {
+            for color in &row[first..] {
+                let (r, g, b) = tv_standard.palette_rgb(*color);
+
+                for _ in 0..repeat {
+                    rgb.extend_from_slice(&[r, g, b]);
+                }
+            }
+        }
+
+        (width, window.height as usize, rgb)
+    }
+
+    pub fn screenshot_png(&self, view: FrameView) -> Vec<u8> {
+        let (width, height, rgb) = self.frame_rgb(view);
+        encode_png(width, height, &rgb)
+    }
+
+    pub fn visible_window(&self) -> VisibleWindow {
+        self.cpu.bus().tia.visible_window()
+    }
+
+    pub fn tv_standard(&self) -> TvStandard {
+        self.cpu.bus().tv_standard()
+    }
+
+    pub fn set_tv_standard(&mut self, standard: Option<TvStandard>) {
+        self.atari().set_tv_standard(standard);
+    }
+
+    // off until a frontend that plays the samples from `audio` turns it on
+    pub fn set_audio_output(&mut self, enabled: bool) {
+        self.atari().tia.set_audio_output(enabled);
+    }
+
+    // interleaved stereo samples produced since the last call
+    pub fn audio(&mut self) -> &[u8] {
+        self.audio_converter.convert(&mut self.cpu.get_bus().tia.audio)
+    }
+
+    // mono samples of the mixed TIA output at `TvStandard::audio_clock_hz`, recorded only while capture is on
+    pub fn set_audio_capture(&mut self, enabled: bool) {
+        self.atari().tia.set_audio_capture(enabled);
+    }
+
+    pub fn take_audio_capture(&mut self) -> Vec<u8> {
+        self.atari().tia.take_audio_capture()
+    }
+
+    pub fn connect(&mut self, port: Port, controller: Box<dyn Controller>) {
+        self.atari().connect(port, controller);
+    }
+
+    pub fn controller_event(&mut self, port: Port, event: ControllerEvent) {
+        self.atari().controller_event(port, event);
+    }
+
+    pub fn switches(&self) -> ConsoleSwitches {
+        self.cpu.bus().riot.switches()
+    }
+
+    pub fn set_switches(&mut self, switches: ConsoleSwitches) {
+        self.atari().riot.set_switches(switches);
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        self.write_state(true)
+    }
+
+    // a state without the frame buffer, a few hundred bytes instead of over 70k
+    pub(crate) fn save_state_without_frame(&self) -> Vec<u8> {
+        self.write_state(false)
+    }
+
+    fn write_state(&self, frame: bool) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        self.cpu.save_state(&mut writer);
+        self.cpu.bus().save_state(&mut writer);
+        writer.put(&frame);
+
+        if frame {
+            writer.put(&self.cpu.bus().tia.frame);
+        }
+
+        writer.finish()
+    }
+
+    // a state that fails to load leaves the system as it was. One saved without its frame buffer keeps the current one
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
+
+        let result = self.read_state(data);
+
+        if result.is_err() {
+            self.read_state(&backup).unwrap();
+        }
+
+        result
+    }
+
+    fn read_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(data)?;
+        self.cpu.load_state(&mut reader)?;
+        self.cpu.get_bus().load_state(&mut reader)?;
+
+        if reader.get()? {
+            self.cpu.get_bus().tia.frame = reader.get()?;
+        }
+
+        reader.finish()
+    }
+}
+
+#[cfg(test)]
+impl System {
+    // a NOP-filled 4K cartridge starting `program` at $F000
+    pub(crate) fn test_rom(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0xEA; 4096];
+        rom[..program.len()].copy_from_slice(program);
+        rom[0xFFC] = 0x00;
+        rom[0xFFD] = 0xF0;
+        rom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tia::Beam;
+
+    const LDA_IMM: u8 = 0xA9;
+    const BIT_ZP: u8 = 0x24;
+    const STA_ZP: u8 = 0x85;
+    const NOP: u8 = 0xEA;
+    const JMP_ABS: u8 = 0x4C;
+    const WSYNC: u8 = 0x02;
+
+    fn system(program: &[u8]) -> System {
+        System::new(System::test_rom(program))
+    }
+
+    // the beam right after the CPU cycle that stored 1 to RAM at $80
+    fn ram_write(system: &mut System) -> Beam {
+        for _ in 0..76 * 10 {
+            system.cycle();
+
+            if system.atari().riot.read(0x80) == 1 {
+                return system.atari().tia.beam();
+            }
+        }
+
+        panic!("$80 was never written");
+    }
+
+    // LDA #1, STA WSYNC, `filler` cycles starting at the first cycle of line 1, STA WSYNC, STA $80
+    fn wsync_twice(filler: &[u8]) -> Beam {
+        let mut program = vec![LDA_IMM, 1, STA_ZP, WSYNC];
+        program.extend_from_slice(filler);
+        program.extend_from_slice(&[STA_ZP, WSYNC, STA_ZP, 0x80]);
+        ram_write(&mut system(&program))
+    }
+
+    #[test]
+    fn first_cycle_after_wsync_is_color_clock_0() {
+        // STA $80 writes on its third cycle, color clocks 6-8 of the line after the WSYNC
+        let beam = ram_write(&mut system(&[LDA_IMM, 1, STA_ZP, WSYNC, STA_ZP, 0x80]));
+        assert_eq!((beam.scanline, beam.color_clock), (1, 9));
+    }
+
+    #[test]
+    fn wsync_on_last_cycle_waits_a_full_line() {
+        // the second WSYNC write lands on cycle 74 of line 1 and is released at the end of it
+        let beam = wsync_twice(&[NOP; 36]);
+        assert_eq!((beam.scanline, beam.color_clock), (2, 9));
+
+        // on cycle 75 the line is already over, so it holds RDY through all of line 2
+        let mut filler = vec![BIT_ZP, 0x81];
+        filler.extend_from_slice(&[NOP; 35]);
+        let beam = wsync_twice(&filler);
+        assert_eq!((beam.scanline, beam.color_clock), (3, 9));
+    }
+
+    #[test]
+    fn corrupt_state_is_rejected() {
+        let mut system = system(&[]);
+        system.run_cycles(101);
+        let state = system.save_state();
+
+        let mut writer = StateWriter::new();
+        system.cpu.save_state(&mut writer);
+        let cpu_end = writer.finish().len();
+
+        let mut writer = StateWriter::bare();
+        system.atari().riot.save_state(&mut writer);
+        let tia = cpu_end + 12 + writer.finish().len();
+
+        let mut writer = StateWriter::bare();
+        system.atari().tia.save_state(&mut writer);
+        let tia_end = tia + writer.finish().len();
+
+        // the CPU ends with cycle, cycles, addr, addr_invalid, ptr, ptr_invalid and opcode, the console starts with the ROM hash and bank offset
+        let cycle = cpu_end - 12;
+        let opcode = cpu_end - 1;
+        let bank_offset = cpu_end + 8;
+        assert_eq!((state[opcode], state[cycle]), (NOP, 2));
+
+        // the TIA starts with draw, scanline, frame_scanlines, frame_number, clocks, visible_first (line 0 here), visible_last,
+        // window, window_candidate, window_candidate_frames, ctr, color_clock, audio_div_ctr and audio_div3_ctr. It ends with
+        // AUDV, AUDF, AUDC and the eight collision latches
+        let visible_first = tia + 18;
+        let window_ystart = tia + 23;
+        let candidate_ystart = tia + 27;
+        let candidate_frames = tia + 30;
+        let audio_div3 = tia + 37;
+        let audc = tia_end - 10;
+        assert_eq!(&state[tia + 17..tia + 22], &[1, 0, 0, 1, 0]);
+
+        let corruptions = [
+            (opcode, 0x02), (cycle, 0), (cycle, 3), (bank_offset, 1), (bank_offset + 1, 0x10),
+            (visible_first, 2), (window_ystart, 1), (candidate_ystart, 1), (candidate_frames, 5), (audio_div3, 3), (audc, 0x10)
+        ];
+
+        for (offset, value) in corruptions {
+            let mut corrupt = state.clone();
+            corrupt[offset] = value;
+
+            assert_eq!(system.load_state(&corrupt), Err(StateError::Invalid), "byte {} = {:02X}", offset, value);
+            assert_eq!(system.save_state(), state);
+        }
+
+        // objects far off the line still load and draw, RESBL, RESM1, RESM0, RESP0 and RESP1 follow the enable bits
+        assert_eq!(&state[tia + 63..tia + 73], &[68, 0, 68, 0, 68, 0, 68, 0, 68, 0]);
+        system.atari().tia.write(0x04, 3);
+        system.atari().tia.write(0x05, 3);
+        let mut far = system.save_state();
+        far[tia + 63..tia + 73].fill(0xFF);
+        assert_eq!(system.load_state(&far), Ok(()));
+        system.run_frame();
+    }
+
+    #[test]
+    fn audio_only_queued_while_output_is_on() {
+        let mut system = system(&[JMP_ABS, 0x00, 0xF0]);
+        system.run_frame();
+        system.run_frame();
+        assert!(system.atari().tia.audio.iter().all(|channel| channel.is_empty()));
+
+        system.set_audio_output(true);
+        system.run_frame();
+        assert!(!system.audio().is_empty());
+
+        system.set_audio_output(false);
+        assert!(system.atari().tia.audio.iter().all(|channel| channel.is_empty()));
+    }
+}
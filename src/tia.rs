@@ -1,19 +1,85 @@
 use std::collections::VecDeque;
 
-pub const NUM_SCANLINES: u16 = 262;
+use crate::{power_on::Rng, state::{Field, StateError, StateReader, StateWriter}, TvStandard};
+
+pub const MAX_SCANLINES: u16 = 320;
 pub const CLOCKS_PER_SCANLINE: u16 = 228;
 
+// how many clocks after the beam passes its aim point a light gun still sees it
+const LIGHT_GUN_CLOCKS: u16 = 15;
+
+// and how many lines, starting with the one it's aimed at
+const LIGHT_GUN_LINES: u16 = 3;
+
+// frames must agree on a new visible window for this long before it is used
+const WINDOW_STABLE_FRAMES: u8 = 5;
+
 #[derive(Clone, Copy, Debug)]
 pub struct TiaAudioSample {
     pub value: u8,
     pub cycles: u16
 }
 
+#[derive(Clone, Copy, Debug)]
+pub enum InputPort {
+    Input4,
+    Input5
+}
+
+// the paddle inputs INPT0-INPT3
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pot {
+    Pot0,
+    Pot1,
+    Pot2,
+    Pot3
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Beam {
+    pub clock: u64,
+    pub frame: u32,
+    pub scanline: u16,
+    pub color_clock: u16,
+    pub tv_standard: TvStandard
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VisibleWindow {
+    pub ystart: u16,
+    pub height: u16
+}
+
+impl Field for VisibleWindow {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.put(&self.ystart);
+        writer.put(&self.height);
+    }
+
+    fn load(reader: &mut StateReader) -> Result<Self, StateError> {
+        Ok(VisibleWindow { ystart: reader.get()?, height: reader.get()? })
+    }
+}
+
+const DEFAULT_WINDOW: VisibleWindow = VisibleWindow { ystart: 31, height: 210 };
+
 pub struct Tia {
-    pub frame: [u8; (CLOCKS_PER_SCANLINE * NUM_SCANLINES) as usize],
+    pub frame: [u8; CLOCKS_PER_SCANLINE as usize * MAX_SCANLINES as usize],
     pub audio: [VecDeque<TiaAudioSample>; 2],
+    audio_level: [u8; 2],
+    audio_output: bool,
+    audio_capture: Option<Vec<u8>>,
     draw: bool,
     scanline: u16,
+    frame_scanlines: u16,
+    frame_number: u32,
+    clocks: u64,
+    visible_first: Option<u16>,
+    visible_last: u16,
+    window: VisibleWindow,
+    window_candidate: VisibleWindow,
+    window_candidate_frames: u8,
+    tv_standard: Option<TvStandard>,
     ctr: u16,
     color_clock: u16,
     audio_div_ctr: [u8; 2],
@@ -21,6 +87,7 @@ pub struct Tia {
     lfsr4: [u8; 2],
     lfsr5: [u8; 2],
     lfsr9: [u16; 2],
+    vsync: u8,
     vblank: u8,
     wsync: bool,
     resmp1: u8,
@@ -58,8 +125,11 @@ pub struct Tia {
     colup0: u8,
     nusiz1: u8,
     nusiz0: u8,
-    inpt5: u8,
-    inpt4: u8,
+    inputs: [bool; 2],
+    input_latches: [bool; 2],
+    light_targets: [Option<(u16, u16)>; 2],
+    pots: [Option<u8>; 4],
+    pot_charge: u16,
     audv: [u8; 2],
     audf: [u8; 2],
     audc: [u8; 2],
@@ -76,10 +146,22 @@ pub struct Tia {
 impl Tia {
     pub fn new() -> Self {
         Tia {
-            frame: [0; (CLOCKS_PER_SCANLINE * NUM_SCANLINES) as usize],
+            frame: [0; CLOCKS_PER_SCANLINE as usize * MAX_SCANLINES as usize],
             audio: [VecDeque::new(), VecDeque::new()],
+            audio_level: [128; 2],
+            audio_output: false,
+            audio_capture: None,
             draw: false,
             scanline: 0,
+            frame_scanlines: 0,
+            frame_number: 0,
+            clocks: 0,
+            visible_first: None,
+            visible_last: 0,
+            window: DEFAULT_WINDOW,
+            window_candidate: DEFAULT_WINDOW,
+            window_candidate_frames: 0,
+            tv_standard: None,
             ctr: 0,
             color_clock: 0,
             audio_div_ctr: [0; 2],
@@ -87,6 +169,7 @@ impl Tia {
             lfsr4: [0xFF; 2],
             lfsr5: [0xFF; 2],
             lfsr9: [0xFFFF; 2],
+            vsync: 0,
             vblank: 0,
             wsync: false,
             resmp1: 0,
@@ -124,8 +207,11 @@ impl Tia {
             colup0: 0,
             nusiz1: 0,
             nusiz0: 0,
-            inpt5: 0,
-            inpt4: 0x80,
+            inputs: [false; 2],
+            input_latches: [false; 2],
+            light_targets: [None; 2],
+            pots: [None; 4],
+            pot_charge: 0,
             audv: [0; 2],
             audf: [0; 2],
             audc: [0; 2],
@@ -148,12 +234,322 @@ impl Tia {
         self.draw
     }
 
-    pub fn input4(&mut self, value: u8) {
-        self.inpt4 = value;
+    // objects power on anywhere in the visible part of the line
+    pub(crate) fn randomize(&mut self, rng: &mut Rng) {
+        self.resp0 = rng.range(68, CLOCKS_PER_SCANLINE);
+        self.resp1 = rng.range(68, CLOCKS_PER_SCANLINE);
+        self.resm0 = rng.range(68, CLOCKS_PER_SCANLINE);
+        self.resm1 = rng.range(68, CLOCKS_PER_SCANLINE);
+        self.resbl = rng.range(68, CLOCKS_PER_SCANLINE);
+    }
+
+    // the frame buffer is saved by `System`, rewind states leave it out
+    pub(crate) fn save_state(&self, writer: &mut StateWriter) {
+        writer.put(&self.draw);
+        writer.put(&self.scanline);
+        writer.put(&self.frame_scanlines);
+        writer.put(&self.frame_number);
+        writer.put(&self.clocks);
+        writer.put(&self.visible_first);
+        writer.put(&self.visible_last);
+        writer.put(&self.window);
+        writer.put(&self.window_candidate);
+        writer.put(&self.window_candidate_frames);
+        writer.put(&self.ctr);
+        writer.put(&self.color_clock);
+        writer.put(&self.audio_div_ctr);
+        writer.put(&self.audio_div3_ctr);
+        writer.put(&self.lfsr4);
+        writer.put(&self.lfsr5);
+        writer.put(&self.lfsr9);
+        writer.put(&self.vsync);
+        writer.put(&self.vblank);
+        writer.put(&self.wsync);
+        writer.put(&self.resmp1);
+        writer.put(&self.resmp0);
+        writer.put(&self.vdelbl);
+        writer.put(&self.vdelp1);
+        writer.put(&self.vdelp0);
+        writer.put(&self.hmbl);
+        writer.put(&self.hmm1);
+        writer.put(&self.hmm0);
+        writer.put(&self.hmp1);
+        writer.put(&self.hmp0);
+        writer.put(&self.enabl);
+        writer.put(&self.enabla);
+        writer.put(&self.enam1);
+        writer.put(&self.enam0);
+        writer.put(&self.resbl);
+        writer.put(&self.resm1);
+        writer.put(&self.resm0);
+        writer.put(&self.resp0);
+        writer.put(&self.resp1);
+        writer.put(&self.grp0);
+        writer.put(&self.grp0a);
+        writer.put(&self.grp1);
+        writer.put(&self.grp1a);
+        writer.put(&self.pf0);
+        writer.put(&self.pf1);
+        writer.put(&self.pf2);
+        writer.put(&self.refp1);
+        writer.put(&self.refp0);
+        writer.put(&self.ctrlpf);
+        writer.put(&self.colubk);
+        writer.put(&self.colupf);
+        writer.put(&self.colup1);
+        writer.put(&self.colup0);
+        writer.put(&self.nusiz1);
+        writer.put(&self.nusiz0);
+        writer.put(&self.inputs);
+        writer.put(&self.input_latches);
+        writer.put(&self.light_targets);
+        writer.put(&self.pots);
+        writer.put(&self.pot_charge);
+        writer.put(&self.audv);
+        writer.put(&self.audf);
+        writer.put(&self.audc);
+        writer.put(&self.cxppmm);
+        writer.put(&self.cxblpf);
+        writer.put(&self.cxm1fb);
+        writer.put(&self.cxm0fb);
+        writer.put(&self.cxp1fb);
+        writer.put(&self.cxp0fb);
+        writer.put(&self.cxm1p);
+        writer.put(&self.cxm0p);
+    }
+
+    // queued and captured audio is dropped rather than saved
+    pub(crate) fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.draw = reader.get()?;
+        self.scanline = reader.get()?;
+        self.frame_scanlines = reader.get()?;
+        self.frame_number = reader.get()?;
+        self.clocks = reader.get()?;
+        self.visible_first = reader.get()?;
+        self.visible_last = reader.get()?;
+        self.window = reader.get()?;
+        self.window_candidate = reader.get()?;
+        self.window_candidate_frames = reader.get()?;
+        self.ctr = reader.get()?;
+        self.color_clock = reader.get()?;
+        self.audio_div_ctr = reader.get()?;
+        self.audio_div3_ctr = reader.get()?;
+        self.lfsr4 = reader.get()?;
+        self.lfsr5 = reader.get()?;
+        self.lfsr9 = reader.get()?;
+        self.vsync = reader.get()?;
+        self.vblank = reader.get()?;
+        self.wsync = reader.get()?;
+        self.resmp1 = reader.get()?;
+        self.resmp0 = reader.get()?;
+        self.vdelbl = reader.get()?;
+        self.vdelp1 = reader.get()?;
+        self.vdelp0 = reader.get()?;
+        self.hmbl = reader.get()?;
+        self.hmm1 = reader.get()?;
+        self.hmm0 = reader.get()?;
+        self.hmp1 = reader.get()?;
+        self.hmp0 = reader.get()?;
+        self.enabl = reader.get()?;
+        self.enabla = reader.get()?;
+        self.enam1 = reader.get()?;
+        self.enam0 = reader.get()?;
+        self.resbl = reader.get()?;
+        self.resm1 = reader.get()?;
+        self.resm0 = reader.get()?;
+        self.resp0 = reader.get()?;
+        self.resp1 = reader.get()?;
+        self.grp0 = reader.get()?;
+        self.grp0a = reader.get()?;
+        self.grp1 = reader.get()?;
+        self.grp1a = reader.get()?;
+        self.pf0 = reader.get()?;
+        self.pf1 = reader.get()?;
+        self.pf2 = reader.get()?;
+        self.refp1 = reader.get()?;
+        self.refp0 = reader.get()?;
+        self.ctrlpf = reader.get()?;
+        self.colubk = reader.get()?;
+        self.colupf = reader.get()?;
+        self.colup1 = reader.get()?;
+        self.colup0 = reader.get()?;
+        self.nusiz1 = reader.get()?;
+        self.nusiz0 = reader.get()?;
+        self.inputs = reader.get()?;
+        self.input_latches = reader.get()?;
+        self.light_targets = reader.get()?;
+        self.pots = reader.get()?;
+        self.pot_charge = reader.get()?;
+        self.audv = reader.get()?;
+        self.audf = reader.get()?;
+        self.audc = reader.get()?;
+        self.cxppmm = reader.get()?;
+        self.cxblpf = reader.get()?;
+        self.cxm1fb = reader.get()?;
+        self.cxm0fb = reader.get()?;
+        self.cxp1fb = reader.get()?;
+        self.cxp0fb = reader.get()?;
+        self.cxm1p = reader.get()?;
+        self.cxm0p = reader.get()?;
+
+        for channel in self.audio.iter_mut() {
+            channel.clear();
+        }
+
+        if let Some(capture) = &mut self.audio_capture {
+            capture.clear();
+        }
+
+        let fits = |window: &VisibleWindow| window.height <= MAX_SCANLINES && window.ystart <= MAX_SCANLINES - window.height;
+
+        // object positions aren't checked, HMOVE can already take them anywhere
+        let valid = self.color_clock <= CLOCKS_PER_SCANLINE
+            && self.scanline < MAX_SCANLINES
+            && self.visible_first.is_none_or(|first| first <= self.visible_last && self.visible_last < MAX_SCANLINES)
+            && fits(&self.window)
+            && fits(&self.window_candidate)
+            && self.window_candidate_frames < WINDOW_STABLE_FRAMES
+            && self.audio_div3_ctr < 3
+            && self.audc.iter().all(|audc| *audc <= 0xF);
+
+        if !valid {
+            return Err(StateError::Invalid);
+        }
+
+        Ok(())
+    }
+
+    pub fn frame_scanlines(&self) -> u16 {
+        self.frame_scanlines
+    }
+
+    pub fn beam(&self) -> Beam {
+        Beam {
+            clock: self.clocks,
+            frame: self.frame_number,
+            scanline: self.scanline,
+            color_clock: self.color_clock,
+            tv_standard: self.tv_standard()
+        }
+    }
+
+    pub fn visible_window(&self) -> VisibleWindow {
+        self.window
+    }
+
+    // the `audio` queues only fill while something drains them, so headless runs don't grow them forever
+    pub fn set_audio_output(&mut self, enabled: bool) {
+        self.audio_output = enabled;
+
+        if !enabled {
+            for channel in self.audio.iter_mut() {
+                channel.clear();
+            }
+        }
+    }
+
+    // records both channels mixed at every audio clock, independent of the `audio` queues
+    pub fn set_audio_capture(&mut self, enabled: bool) {
+        self.audio_capture = enabled.then(Vec::new);
+    }
+
+    pub fn take_audio_capture(&mut self) -> Vec<u8> {
+        self.audio_capture.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    // `None` detects the standard from the number of scanlines the ROM produces
+    pub fn set_tv_standard(&mut self, standard: Option<TvStandard>) {
+        self.tv_standard = standard;
+    }
+
+    pub fn tv_standard(&self) -> TvStandard {
+        self.tv_standard.unwrap_or_else(|| TvStandard::detect(self.frame_scanlines))
+    }
+
+    fn end_frame(&mut self) {
+        self.frame_scanlines = self.scanline;
+        self.frame_number = self.frame_number.wrapping_add(1);
+        self.scanline = 0;
+        self.draw = true;
+
+        let height = self.tv_standard().visible_scanlines();
+
+        // center the lines the ROM left VBLANK off for
+        let candidate = match self.visible_first.take() {
+            Some(first) => {
+                let kernel = self.visible_last - first + 1;
+                let ystart = first.saturating_sub(height.saturating_sub(kernel) / 2);
+
+                VisibleWindow { ystart: ystart.min(MAX_SCANLINES - height), height }
+            }
+            None => VisibleWindow { ystart: self.window.ystart.min(MAX_SCANLINES - height), height }
+        };
+
+        if candidate == self.window {
+            self.window_candidate_frames = 0;
+        } else if candidate == self.window_candidate {
+            self.window_candidate_frames += 1;
+
+            if self.window_candidate_frames >= WINDOW_STABLE_FRAMES {
+                self.window = candidate;
+                self.window_candidate_frames = 0;
+            }
+        } else {
+            self.window_candidate = candidate;
+            self.window_candidate_frames = 1;
+        }
+    }
+
+    // position is the number of scanlines the paddle's capacitor takes to charge after VBLANK stops dumping it
+    pub fn set_paddle(&mut self, pot: Pot, position: u8) {
+        self.pots[pot as usize] = Some(position);
     }
 
-    pub fn input5(&mut self, value: u8) {
-        self.inpt5 = value;
+    pub fn disconnect_paddle(&mut self, pot: Pot) {
+        self.pots[pot as usize] = None;
+    }
+
+    fn pot_input(&self, index: usize) -> u8 {
+        match self.pots[index] {
+            Some(position) if self.vblank & (1 << 7) == 0 && self.pot_charge >= position as u16 => 0x80,
+            _ => 0
+        }
+    }
+
+    pub fn set_input(&mut self, port: InputPort, pressed: bool) {
+        let index = port as usize;
+        self.inputs[index] = pressed;
+
+        if pressed && self.vblank & (1 << 6) != 0 {
+            self.input_latches[index] = true;
+        }
+    }
+
+    // a light gun aimed at (color clock, scanline) pulls its input low while the beam lights up that point
+    pub fn set_light_target(&mut self, port: InputPort, target: Option<(u16, u16)>) {
+        self.light_targets[port as usize] = target;
+    }
+
+    fn light_sensed(&self, index: usize) -> bool {
+        match self.light_targets[index] {
+            Some((x, y)) => {
+                self.vblank & (1 << 1) == 0
+                    && self.scanline >= y && self.scanline - y < LIGHT_GUN_LINES
+                    && self.color_clock >= x && self.color_clock - x < LIGHT_GUN_CLOCKS
+            }
+            None => false
+        }
+    }
+
+    // with VBLANK bit 6 set a press is held until the latch is disabled
+    fn trigger_input(&self, index: usize) -> u8 {
+        let pressed = if self.vblank & (1 << 6) != 0 {
+            self.input_latches[index]
+        } else {
+            self.inputs[index] || self.light_sensed(index)
+        };
+
+        if pressed { 0 } else { 0x80 }
     }
 
     pub fn drew(&mut self) {
@@ -192,35 +588,61 @@ impl Tia {
         false
     }
 
+    fn copy_offsets(nusiz: u8) -> &'static [u16] {
+        match nusiz & 0b111 {
+            1 => &[0, 16],
+            2 => &[0, 32],
+            3 => &[0, 16, 32],
+            4 => &[0, 64],
+            6 => &[0, 32, 64],
+            _ => &[0]
+        }
+    }
+
     fn player_pixel_extra(&self, clock: u16, player: u8, pos: u16, reflect: bool, nusiz: u8) -> bool {
+        let size = match nusiz & 0b111 {
+            5 => 2,
+            7 => 4,
+            _ => 1
+        };
+
+        Tia::copy_offsets(nusiz).iter().any(|offset| self.player_pixel(clock, player, pos.wrapping_add(*offset), size, reflect))
+    }
+
+    fn object_pixel(&self, clock: u16, pos: u16, size: u16) -> bool {
+        clock >= pos && clock - pos < size
+    }
+
+    fn missile_pixel(&self, clock: u16, pos: u16, nusiz: u8) -> bool {
+        let size = 1 << ((nusiz >> 4) & 0b11);
+
+        Tia::copy_offsets(nusiz).iter().any(|offset| self.object_pixel(clock, pos.wrapping_add(*offset), size))
+    }
+
+    fn resmp_offset(nusiz: u8) -> u16 {
         match nusiz & 0b111 {
-            0 => self.player_pixel(clock, player, pos, 1, reflect),
-            1 => {
-                self.player_pixel(clock, player, pos, 1, reflect)
-                || self.player_pixel(clock, player, pos + 16, 1, reflect)
-            }
-            2 => {
-                self.player_pixel(clock, player, pos, 1, reflect)
-                || self.player_pixel(clock, player, pos + 32, 1, reflect)
-            }
-            3 => {
-                self.player_pixel(clock, player, pos, 1, reflect)
-                || self.player_pixel(clock, player, pos + 16, 1, reflect)
-                || self.player_pixel(clock, player, pos + 32, 1, reflect)
-            }
-            4 => {
-                self.player_pixel(clock, player, pos, 1, reflect)
-                || self.player_pixel(clock, player, pos + 64, 1, reflect)
-            }
-            5 => self.player_pixel(clock, player, pos, 2, reflect),
-            6 => {
-                self.player_pixel(clock, player, pos, 1, reflect)
-                || self.player_pixel(clock, player, pos + 32, 1, reflect)
-                || self.player_pixel(clock, player, pos + 64, 1, reflect)
-            }
-            7 => self.player_pixel(clock, player, pos, 4, reflect),
-            _ => false
+            5 => 6,
+            7 => 10,
+            _ => 3
+        }
+    }
+
+    // a missile locked to its player stays hidden and follows the player's center
+    fn lock_missiles(&mut self) {
+        if self.resmp0 != 0 {
+            self.resm0 = self.resp0.wrapping_add(Tia::resmp_offset(self.nusiz0));
         }
+
+        if self.resmp1 != 0 {
+            self.resm1 = self.resp1.wrapping_add(Tia::resmp_offset(self.nusiz1));
+        }
+    }
+
+    fn ball_pixel(&self, clock: u16) -> bool {
+        let enabled = if self.vdelbl == 0 { self.enabl != 0 } else { self.enabla != 0 };
+        let size = 1 << ((self.ctrlpf >> 4) & 0b11);
+
+        enabled && self.object_pixel(clock, self.resbl, size)
     }
 
     fn player_graphic(&self, player0: bool) -> u8 {
@@ -305,7 +727,10 @@ impl Tia {
                 if self.audio_div3_ctr == 0 {
                     if self.audio_div_ctr[chan] >= self.audf[chan] {
                         let value = self.audio_cycle(chan);
-                        self.audio[chan].push_back(TiaAudioSample { value, cycles: self.ctr });
+                        self.audio_level[chan] = value;
+                        if self.audio_output {
+                            self.audio[chan].push_back(TiaAudioSample { value, cycles: self.ctr });
+                        }
                         self.audio_div_ctr[chan] = 0xFF;
                     }
 
@@ -314,7 +739,10 @@ impl Tia {
             } else {
                 if self.audio_div_ctr[chan] >= self.audf[chan] {
                     let value = self.audio_cycle(chan);
-                    self.audio[chan].push_back(TiaAudioSample { value, cycles: self.ctr });
+                    self.audio_level[chan] = value;
+                    if self.audio_output {
+                        self.audio[chan].push_back(TiaAudioSample { value, cycles: self.ctr });
+                    }
                     self.audio_div_ctr[chan] = 0xFF;
                 }
 
@@ -322,6 +750,10 @@ impl Tia {
             }
         }
 
+        if let Some(capture) = &mut self.audio_capture {
+            capture.push(((self.audio_level[0] as u16 + self.audio_level[1] as u16) / 2) as u8);
+        }
+
         self.audio_div3_ctr += 1;
 
         if self.audio_div3_ctr == 3 {
@@ -331,27 +763,51 @@ impl Tia {
 
     pub fn cycle(&mut self) {
         self.ctr = self.ctr.wrapping_add(1);
+        self.clocks = self.clocks.wrapping_add(1);
 
         if self.color_clock == CLOCKS_PER_SCANLINE {
             self.color_clock = 0;
             self.scanline += 1;
-            self.wsync = false;
+
+            if self.vblank & (1 << 7) == 0 {
+                self.pot_charge = self.pot_charge.saturating_add(1);
+            }
+
+            // VSYNC never came, so force a frame instead of running off the end of the buffer
+            if self.scanline == MAX_SCANLINES {
+                self.end_frame();
+            }
         }
 
         if self.color_clock == 0 || self.color_clock == 114 {
             self.audio_clockgen();
         }
 
+        if self.color_clock == 68 && self.vblank & (1 << 1) == 0 {
+            self.visible_first.get_or_insert(self.scanline);
+            self.visible_last = self.scanline;
+        }
+
+        self.lock_missiles();
+
+        if self.vblank & (1 << 6) != 0 {
+            for port in 0..2 {
+                if self.light_sensed(port) {
+                    self.input_latches[port] = true;
+                }
+            }
+        }
+
         let index = self.scanline as usize * CLOCKS_PER_SCANLINE as usize + self.color_clock as usize;
 
         if index >= self.frame.len() {
-            self.color_clock += 1;
+            self.advance();
             return;
         }
 
         if self.vblank & (1 << 1) != 0 || self.color_clock < 68 {
             self.frame[index as usize] = 0;
-            self.color_clock += 1;
+            self.advance();
             return;
         }
 
@@ -368,41 +824,35 @@ impl Tia {
         let p0_pixel = self.player_pixel_extra(self.color_clock, self.player_graphic(true), self.resp0, self.refp0 & (1 << 3) != 0, self.nusiz0);
         let p1_pixel = self.player_pixel_extra(self.color_clock, self.player_graphic(false), self.resp1, self.refp1 & (1 << 3) != 0, self.nusiz1);
 
-        let mut color: Option<u8> = None;
-        let ball_size = (((self.ctrlpf >> 4) & 0b11) + 1) * 2;
+        let bl_pixel = self.ball_pixel(self.color_clock);
+        let m0_pixel = self.enam0 != 0 && self.resmp0 == 0 && self.missile_pixel(self.color_clock, self.resm0, self.nusiz0);
+        let m1_pixel = self.enam1 != 0 && self.resmp1 == 0 && self.missile_pixel(self.color_clock, self.resm1, self.nusiz1);
 
-        if self.ctrlpf & (1 << 2) != 0 && pf_pixel {
-            color = Some(self.colupf);
-        } else {
-            if p0_pixel || p1_pixel {
-                if p1_pixel {
-                    color = Some(self.colup1);
-                }
+        let priority = self.ctrlpf & (1 << 2) != 0;
 
-                if p0_pixel {
-                    color = Some(self.colup0);
-                }
-            } else  if pf_pixel {
-                color = Some(self.colupf);
-            }
-        }
-
-        let bl_enable = if self.vdelbl == 0 { self.enabl != 0 } else { self.enabla != 0 };
-        let bl_pixel = self.color_clock >= self.resbl && self.color_clock < self.resbl + ball_size as u16 && bl_enable;
-        let m0_pixel = self.color_clock == self.resm0 && self.enam0 != 0 && self.resmp0 == 0;
-        let m1_pixel = self.color_clock == self.resm1 && self.enam1 != 0 && self.resmp1 == 0;
+        // score mode colors each half of the playfield like its player, but the priority bit overrides it
+        let pf_color = if self.ctrlpf & (1 << 1) != 0 && !priority {
+            if x < 80 { self.colup0 } else { self.colup1 }
+        } else {
+            self.colupf
+        };
 
-        if color.is_none() && bl_pixel {
-            color = Some(self.colupf);
-        }
+        let pf_layer = if pf_pixel {
+            Some(pf_color)
+        } else if bl_pixel {
+            Some(self.colupf)
+        } else {
+            None
+        };
 
-        if color.is_none() && m0_pixel {
-            color = Some(self.colup0);
-        }
+        let p0_layer = if p0_pixel || m0_pixel { Some(self.colup0) } else { None };
+        let p1_layer = if p1_pixel || m1_pixel { Some(self.colup1) } else { None };
 
-        if color.is_none() && m1_pixel {
-            color = Some(self.colup1);
-        }
+        let color = if priority {
+            pf_layer.or(p0_layer).or(p1_layer)
+        } else {
+            p0_layer.or(p1_layer).or(pf_layer)
+        };
 
         self.frame[index as usize] = color.unwrap_or(self.colubk);
 
@@ -466,6 +916,11 @@ impl Tia {
             self.cxm0p |= 1 << 6;
         }
 
+        self.advance();
+    }
+
+    // RDY is released once per line, a WSYNC written on the last cycle of a line waits for the next one
+    fn advance(&mut self) {
         self.color_clock += 1;
 
         if self.color_clock == CLOCKS_PER_SCANLINE {
@@ -477,12 +932,12 @@ impl Tia {
         match addr & 0x108f {
             0x000F => 0, // asteroids writes here ??
             0x000E => 0, // asteroids writes here ??
-            0x000D => self.inpt5, //INPT5 (input port 5, trigger 1)
-            0x000C => self.inpt4, //INPT4 (input port 4, trigger 0)
-            0x000B => 0, //INPT3 (input port 3, pot 3)
-            0x000A => 0, //INPT2 (input port 2, pot 2)
-            0x0009 => 0, //INPT1 (input port 1, pot 1)
-            0x0008 => 0, //INPT0 (input port 0, pot 0)
+            0x000D => self.trigger_input(1), //INPT5 (input port 5, trigger 1)
+            0x000C => self.trigger_input(0), //INPT4 (input port 4, trigger 0)
+            0x000B => self.pot_input(3), //INPT3 (input port 3, pot 3)
+            0x000A => self.pot_input(2), //INPT2 (input port 2, pot 2)
+            0x0009 => self.pot_input(1), //INPT1 (input port 1, pot 1)
+            0x0008 => self.pot_input(0), //INPT0 (input port 0, pot 0)
             0x0007 => self.cxppmm, //CXPPMM (collision of players and missiles)
             0x0006 => self.cxblpf, //CXBLPF (collision of ball with playfield)
             0x0005 => self.cxm1fb, //CXM1FB (collision of missile 1 with playfield-ball)
@@ -534,12 +989,12 @@ impl Tia {
             }
             0x0029 => { //RESMP1 (reset missile 1 to player 1)
                 self.resmp1 = value & 2;
-                self.resm1 = self.resp1 + 4;
-             }
+                self.lock_missiles();
+            }
             0x0028 => { //RESMP0 (reset missile 0 to player 0)
                 self.resmp0 = value & 2;
-                self.resm0 = self.resp0 + 4;
-             }
+                self.lock_missiles();
+            }
             0x0027 => self.vdelbl = value & 1, //VDELBL (vertical delay ball)
             0x0026 => self.vdelp1 = value & 1, //VDELP1 (vertical delay player 1)
             0x0025 => self.vdelp0 = value & 1, //VDELP0 (vertical delay player 0)
@@ -589,14 +1044,348 @@ impl Tia {
             0x0004 => self.nusiz0 = value, //NUSIZ0 (number-size player-missile 0)
             0x0003 => self.color_clock = 0, //RSYNC (reset horizontal sync counter)
             0x0002 => self.wsync = true, //WSYNC (wait for leading edge of horizontal blank)
-            0x0001 => self.vblank = value, //VBLANK (vertical blank set-clear)
+            0x0001 => { //VBLANK (vertical blank set-clear)
+                if value & (1 << 7) != 0 {
+                    self.pot_charge = 0;
+                }
+
+                if value & (1 << 6) != 0 {
+                    self.input_latches[0] |= self.inputs[0];
+                    self.input_latches[1] |= self.inputs[1];
+                } else {
+                    self.input_latches = [false; 2];
+                }
+
+                self.vblank = value;
+            }
             0x0000 => { //VSYNC (vertical sync set-clear)
-                if value & (1 << 1) != 0 {
-                    self.scanline = 0;
-                    self.draw = true;
+                if value & (1 << 1) != 0 && self.vsync == 0 {
+                    self.end_frame();
                 }
+
+                self.vsync = value & (1 << 1);
             }
             _ => panic!("Unknown TIA write register: 0x{:04X}", addr)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COLUBK: u8 = 0x00;
+    const COLUPF: u8 = 0x22;
+    const COLUP0: u8 = 0x44;
+    const COLUP1: u8 = 0x66;
+
+    fn tia() -> Tia {
+        let mut tia = Tia::new();
+        tia.write(0x09, COLUBK);
+        tia.write(0x08, COLUPF);
+        tia.write(0x06, COLUP0);
+        tia.write(0x07, COLUP1);
+        tia
+    }
+
+    // draws a whole scanline and returns it, indexed by color clock
+    fn line(tia: &mut Tia) -> Vec<u8> {
+        for _ in 0..CLOCKS_PER_SCANLINE {
+            tia.cycle();
+        }
+
+        let start = tia.scanline as usize * CLOCKS_PER_SCANLINE as usize;
+        tia.frame[start..start + CLOCKS_PER_SCANLINE as usize].to_vec()
+    }
+
+    #[test]
+    fn resmp_locks_missile_to_player_center() {
+        for (nusiz, offset) in [(0x00, 3), (0x05, 6), (0x07, 10)] {
+            let mut tia = tia();
+            tia.write(0x04, nusiz);
+            tia.write(0x1D, 2);
+            tia.resp0 = 100;
+            tia.write(0x28, 2);
+
+            assert_eq!(tia.resm0, 100 + offset, "nusiz {:02X}", nusiz);
+            assert!(!line(&mut tia).contains(&COLUP0), "missile drawn while locked, nusiz {:02X}", nusiz);
+
+            // move the player one clock right, the missile follows it
+            tia.write(0x20, 0xF0);
+            tia.write(0x2A, 0);
+            assert_eq!(tia.resp0, 101);
+            assert!(!line(&mut tia).contains(&COLUP0), "missile drawn while locked, nusiz {:02X}", nusiz);
+            assert_eq!(tia.resm0, 101 + offset, "nusiz {:02X}", nusiz);
+
+            tia.write(0x2B, 0);
+            tia.write(0x28, 0);
+            let row = line(&mut tia);
+            let x = (101 + offset) as usize;
+
+            assert_eq!(tia.resm0, 101 + offset, "nusiz {:02X}", nusiz);
+            assert_eq!(row[x - 1], COLUBK, "nusiz {:02X}", nusiz);
+            assert_eq!(row[x], COLUP0, "nusiz {:02X}", nusiz);
+        }
+    }
+
+    // the color clocks of a scanline drawn in `color`
+    fn drawn(row: &[u8], color: u8) -> Vec<u16> {
+        (0..row.len() as u16).filter(|x| row[*x as usize] == color).collect()
+    }
+
+    #[test]
+    fn missile_copies_follow_nusiz() {
+        for (nusiz, offsets) in [
+            (0, &[0][..]), (1, &[0, 16]), (2, &[0, 32]), (3, &[0, 16, 32]),
+            (4, &[0, 64]), (5, &[0]), (6, &[0, 32, 64]), (7, &[0])
+        ] {
+            let mut tia = tia();
+            tia.write(0x04, 0x10 | nusiz); // two clocks wide
+            tia.write(0x1D, 2);
+            tia.resm0 = 100;
+
+            let expected: Vec<u16> = offsets.iter().flat_map(|offset| [100 + offset, 101 + offset]).collect();
+            assert_eq!(drawn(&line(&mut tia), COLUP0), expected, "nusiz {:02X}", nusiz);
+        }
+    }
+
+    #[test]
+    fn ball_widths() {
+        for (ctrlpf, width) in [(0x00, 1), (0x10, 2), (0x20, 4), (0x30, 8)] {
+            let mut tia = tia();
+            tia.write(0x0A, ctrlpf);
+            tia.write(0x1F, 2);
+            tia.resbl = 100;
+
+            assert_eq!(drawn(&line(&mut tia), COLUPF), (100..100 + width).collect::<Vec<u16>>(), "ctrlpf {:02X}", ctrlpf);
+        }
+    }
+
+    #[test]
+    fn ball_vertical_delay() {
+        let mut tia = tia();
+        tia.resbl = 100;
+        tia.write(0x27, 1);
+        tia.write(0x1F, 2);
+
+        // the delayed ball only takes ENABL when GRP1 is written
+        assert!(drawn(&line(&mut tia), COLUPF).is_empty());
+        tia.write(0x1C, 0);
+        assert_eq!(drawn(&line(&mut tia), COLUPF), [100]);
+
+        tia.write(0x1F, 0);
+        assert_eq!(drawn(&line(&mut tia), COLUPF), [100]);
+        tia.write(0x1C, 0);
+        assert!(drawn(&line(&mut tia), COLUPF).is_empty());
+
+        // without VDELBL the ball follows ENABL straight away
+        tia.write(0x27, 0);
+        tia.write(0x1F, 2);
+        assert_eq!(drawn(&line(&mut tia), COLUPF), [100]);
+    }
+
+    // objects at x = 100 (color clock 168), the playfield covers the whole line when `playfield` is set
+    fn pixel(ctrlpf: u8, playfield: bool, objects: &[u16]) -> u8 {
+        let mut tia = tia();
+        tia.write(0x0A, ctrlpf);
+
+        if playfield {
+            tia.write(0x0D, 0xF0);
+            tia.write(0x0E, 0xFF);
+            tia.write(0x0F, 0xFF);
+        }
+
+        for register in objects {
+            match register {
+                0x1B | 0x1C => tia.write(*register, 0xFF),
+                _ => tia.write(*register, 2)
+            }
+        }
+
+        tia.resp0 = 168;
+        tia.resp1 = 168;
+        tia.resm0 = 168;
+        tia.resm1 = 168;
+        tia.resbl = 168;
+        line(&mut tia)[168]
+    }
+
+    const P0: u16 = 0x1B;
+    const P1: u16 = 0x1C;
+    const M0: u16 = 0x1D;
+    const M1: u16 = 0x1E;
+    const BL: u16 = 0x1F;
+
+    #[test]
+    fn priority_table() {
+        // score mode colors the right half of the playfield, where the objects are, like player 1
+        const SCORE: u8 = COLUP1;
+        const MODES: [u8; 4] = [0b000, 0b010, 0b100, 0b110];
+
+        // objects, playfield, then the color for each of MODES
+        let table: [(&[u16], bool, [u8; 4]); 18] = [
+            (&[], false, [COLUBK, COLUBK, COLUBK, COLUBK]),
+            (&[], true, [COLUPF, SCORE, COLUPF, COLUPF]),
+            (&[BL], false, [COLUPF, COLUPF, COLUPF, COLUPF]),
+            (&[BL], true, [COLUPF, SCORE, COLUPF, COLUPF]),
+            (&[P0], false, [COLUP0, COLUP0, COLUP0, COLUP0]),
+            (&[P0], true, [COLUP0, COLUP0, COLUPF, COLUPF]),
+            (&[M0], false, [COLUP0, COLUP0, COLUP0, COLUP0]),
+            (&[M0], true, [COLUP0, COLUP0, COLUPF, COLUPF]),
+            (&[P1], false, [COLUP1, COLUP1, COLUP1, COLUP1]),
+            (&[P1], true, [COLUP1, COLUP1, COLUPF, COLUPF]),
+            (&[M1], false, [COLUP1, COLUP1, COLUP1, COLUP1]),
+            (&[M1], true, [COLUP1, COLUP1, COLUPF, COLUPF]),
+            (&[M0, P1], false, [COLUP0, COLUP0, COLUP0, COLUP0]),
+            (&[M0, P1], true, [COLUP0, COLUP0, COLUPF, COLUPF]),
+            (&[BL, P0], false, [COLUP0, COLUP0, COLUPF, COLUPF]),
+            (&[BL, M1], false, [COLUP1, COLUP1, COLUPF, COLUPF]),
+            (&[P0, M0, P1, M1, BL], false, [COLUP0, COLUP0, COLUPF, COLUPF]),
+            (&[P0, M0, P1, M1, BL], true, [COLUP0, COLUP0, COLUPF, COLUPF])
+        ];
+
+        for (objects, playfield, colors) in table {
+            for (ctrlpf, color) in MODES.into_iter().zip(colors) {
+                assert_eq!(pixel(ctrlpf, playfield, objects), color, "ctrlpf {:03b} playfield {} objects {:?}", ctrlpf, playfield, objects);
+            }
+        }
+    }
+
+    #[test]
+    fn score_mode_with_playfield_priority() {
+        let score = |ctrlpf: u8, objects: &[u16]| {
+            let mut tia = tia();
+            tia.write(0x0A, ctrlpf);
+            tia.write(0x0D, 0xF0);
+            tia.write(0x0E, 0xFF);
+            tia.write(0x0F, 0xFF);
+
+            for register in objects {
+                tia.write(*register, 0xFF);
+            }
+
+            // each player over the other half of the playfield
+            tia.resp1 = 100;
+            tia.resp0 = 200;
+            let row = line(&mut tia);
+            (row[80], row[100], row[180], row[200])
+        };
+
+        // each half in its player's color, players still on top
+        assert_eq!(score(0b010, &[]), (COLUP0, COLUP0, COLUP1, COLUP1));
+        assert_eq!(score(0b010, &[P0, P1]), (COLUP0, COLUP1, COLUP1, COLUP0));
+
+        // the priority bit wins over score mode, so the playfield keeps its own color and covers the players
+        assert_eq!(score(0b110, &[]), (COLUPF, COLUPF, COLUPF, COLUPF));
+        assert_eq!(score(0b110, &[P0, P1]), (COLUPF, COLUPF, COLUPF, COLUPF));
+    }
+
+    #[test]
+    fn forced_tv_standard_sizes_window() {
+        for (standard, height) in [(None, 210), (Some(TvStandard::Pal), 250), (Some(TvStandard::Ntsc), 210)] {
+            let mut tia = tia();
+            tia.set_tv_standard(standard);
+
+            for _ in 0..=WINDOW_STABLE_FRAMES {
+                for _ in 0..262 {
+                    line(&mut tia);
+                }
+
+                tia.write(0x00, 2);
+                tia.write(0x00, 0);
+            }
+
+            assert_eq!(tia.visible_window().height, height, "{:?}", standard);
+        }
+    }
+
+    // a 262 line frame with VBLANK off for the 192 lines from `first`, ended by VSYNC
+    fn kernel_frame(tia: &mut Tia, first: u16) {
+        loop {
+            line(tia);
+
+            // the next line starts on the following scanline
+            match tia.scanline + 1 {
+                next if next == first => tia.write(0x01, 0),
+                next if next == first + 192 => tia.write(0x01, 2),
+                262 => break,
+                _ => ()
+            }
+        }
+
+        tia.write(0x00, 2);
+        tia.write(0x00, 0);
+    }
+
+    #[test]
+    fn window_follows_a_stable_kernel() {
+        let mut tia = tia();
+        let moved = VisibleWindow { ystart: 51, height: 210 };
+
+        for _ in 0..WINDOW_STABLE_FRAMES {
+            kernel_frame(&mut tia, 40);
+        }
+        assert_eq!(tia.visible_window(), DEFAULT_WINDOW);
+
+        // a frame back at the old position starts the count again
+        for _ in 1..WINDOW_STABLE_FRAMES {
+            kernel_frame(&mut tia, 60);
+        }
+        kernel_frame(&mut tia, 40);
+        for _ in 1..WINDOW_STABLE_FRAMES {
+            kernel_frame(&mut tia, 60);
+        }
+        assert_eq!(tia.visible_window(), DEFAULT_WINDOW);
+
+        kernel_frame(&mut tia, 60);
+        assert_eq!(tia.visible_window(), moved);
+
+        // frames without a kernel keep the window where it is
+        for _ in 0..=WINDOW_STABLE_FRAMES {
+            for _ in 0..262 {
+                line(&mut tia);
+            }
+
+            tia.write(0x00, 2);
+            tia.write(0x00, 0);
+        }
+        assert_eq!(tia.visible_window(), moved);
+    }
+
+    #[test]
+    fn frame_forced_at_max_scanlines() {
+        let mut tia = tia();
+
+        for _ in 0..MAX_SCANLINES - 1 {
+            line(&mut tia);
+        }
+        assert!(!tia.draw());
+
+        line(&mut tia);
+        line(&mut tia);
+        assert!(tia.draw());
+        assert_eq!(tia.frame_scanlines(), MAX_SCANLINES);
+        assert_eq!(tia.beam().frame, 1);
+        assert_eq!(tia.beam().scanline, 0);
+    }
+
+    #[test]
+    fn light_gun_sees_beam_only_near_aim_point() {
+        let mut tia = tia();
+        tia.set_light_target(InputPort::Input4, Some((150, 20)));
+
+        let sensed = (0..40).map(|_| {
+            let mut seen = false;
+
+            for _ in 0..CLOCKS_PER_SCANLINE {
+                tia.cycle();
+                seen |= tia.read(0x0C) & 0x80 == 0;
+            }
+
+            seen
+        }).collect::<Vec<bool>>();
+
+        let lines = sensed.iter().enumerate().filter(|(_, seen)| **seen).map(|(line, _)| line as u16).collect::<Vec<u16>>();
+        assert_eq!(lines, (20..20 + LIGHT_GUN_LINES).collect::<Vec<u16>>());
+    }
+}
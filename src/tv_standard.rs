@@ -0,0 +1,62 @@
+use crate::{palette_rgb, palette_rgb_pal, palette_rgb_secam, tia::CLOCKS_PER_SCANLINE};
+
+// frames longer than this are assumed to come from a 50Hz ROM
+const PAL_DETECT_SCANLINES: u16 = 287;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TvStandard {
+    Ntsc,
+    Pal,
+    Pal60,
+    Secam
+}
+
+impl TvStandard {
+    pub fn detect(scanlines: u16) -> Self {
+        if scanlines > PAL_DETECT_SCANLINES {
+            TvStandard::Pal
+        } else {
+            TvStandard::Ntsc
+        }
+    }
+
+    pub fn scanlines(self) -> u16 {
+        match self {
+            TvStandard::Ntsc | TvStandard::Pal60 => 262,
+            TvStandard::Pal | TvStandard::Secam => 312
+        }
+    }
+
+    pub fn visible_scanlines(self) -> u16 {
+        match self {
+            TvStandard::Ntsc | TvStandard::Pal60 => 210,
+            TvStandard::Pal | TvStandard::Secam => 250
+        }
+    }
+
+    pub fn color_clock_hz(self) -> u32 {
+        match self {
+            TvStandard::Ntsc => 3_579_545,
+            TvStandard::Pal | TvStandard::Pal60 => 3_546_894,
+            TvStandard::Secam => 3_562_125
+        }
+    }
+
+    // the TIA clocks its audio twice per scanline
+    pub fn audio_clock_hz(self) -> u32 {
+        let clocks = CLOCKS_PER_SCANLINE as u32 / 2;
+        (self.color_clock_hz() + clocks / 2) / clocks
+    }
+
+    pub fn frame_rate(self) -> f32 {
+        self.color_clock_hz() as f32 / (CLOCKS_PER_SCANLINE as u32 * self.scanlines() as u32) as f32
+    }
+
+    pub fn palette_rgb(self, color: u8) -> (u8, u8, u8) {
+        match self {
+            TvStandard::Ntsc => palette_rgb(color),
+            TvStandard::Pal | TvStandard::Pal60 => palette_rgb_pal(color),
+            TvStandard::Secam => palette_rgb_secam(color)
+        }
+    }
+}
@@ -0,0 +1,380 @@
+// Boots small hand-assembled TIA test programs and the homebrew test ROMs in tests/roms headlessly and
+// compares the frames they draw against the golden images in tests/golden. Run with UPDATE_GOLDEN=1 to
+// rewrite the goldens after an intended change, mismatches leave the actual frame and a diff image in
+// target/golden-diff.
+
+use std::{fs, path::PathBuf};
+
+use atari2600::{controller::{ControllerEvent, Port}, tia::CLOCKS_PER_SCANLINE, System, TvStandard};
+
+const HBLANK: usize = 68;
+const WIDTH: usize = CLOCKS_PER_SCANLINE as usize - HBLANK;
+
+mod tia {
+    pub const VSYNC: u8 = 0x00;
+    pub const VBLANK: u8 = 0x01;
+    pub const WSYNC: u8 = 0x02;
+    pub const NUSIZ0: u8 = 0x04;
+    pub const NUSIZ1: u8 = 0x05;
+    pub const COLUP0: u8 = 0x06;
+    pub const COLUP1: u8 = 0x07;
+    pub const COLUPF: u8 = 0x08;
+    pub const COLUBK: u8 = 0x09;
+    pub const CTRLPF: u8 = 0x0A;
+    pub const REFP1: u8 = 0x0C;
+    pub const PF0: u8 = 0x0D;
+    pub const PF1: u8 = 0x0E;
+    pub const PF2: u8 = 0x0F;
+    pub const RESP0: u8 = 0x10;
+    pub const RESP1: u8 = 0x11;
+    pub const RESM0: u8 = 0x12;
+    pub const RESM1: u8 = 0x13;
+    pub const RESBL: u8 = 0x14;
+    pub const GRP0: u8 = 0x1B;
+    pub const GRP1: u8 = 0x1C;
+    pub const ENAM0: u8 = 0x1D;
+    pub const ENAM1: u8 = 0x1E;
+    pub const ENABL: u8 = 0x1F;
+    pub const HMM0: u8 = 0x22;
+    pub const HMM1: u8 = 0x23;
+    pub const HMBL: u8 = 0x24;
+    pub const HMOVE: u8 = 0x2A;
+    pub const INPT4: u8 = 0x0C;
+    pub const SWCHA: u16 = 0x0280;
+}
+
+// just enough of a 6502 assembler for the test programs, branches only go backwards
+struct Asm {
+    code: Vec<u8>
+}
+
+impl Asm {
+    const ORIGIN: u16 = 0xF000;
+
+    fn new() -> Self {
+        Asm { code: Vec::new() }
+    }
+
+    fn label(&self) -> u16 {
+        Asm::ORIGIN + self.code.len() as u16
+    }
+
+    fn op(&mut self, bytes: &[u8]) -> &mut Self {
+        self.code.extend_from_slice(bytes);
+        self
+    }
+
+    fn lda(&mut self, value: u8) -> &mut Self { self.op(&[0xA9, value]) }
+    fn ldx(&mut self, value: u8) -> &mut Self { self.op(&[0xA2, value]) }
+    fn ldy(&mut self, value: u8) -> &mut Self { self.op(&[0xA0, value]) }
+    fn lda_zp(&mut self, addr: u8) -> &mut Self { self.op(&[0xA5, addr]) }
+    fn lda_abs(&mut self, addr: u16) -> &mut Self { self.op(&[0xAD, addr as u8, (addr >> 8) as u8]) }
+    fn sta(&mut self, addr: u8) -> &mut Self { self.op(&[0x85, addr]) }
+    fn sty(&mut self, addr: u8) -> &mut Self { self.op(&[0x84, addr]) }
+    fn nop(&mut self, count: usize) -> &mut Self { self.op(&vec![0xEA; count]) }
+    fn dex(&mut self) -> &mut Self { self.op(&[0xCA]) }
+    fn dey(&mut self) -> &mut Self { self.op(&[0x88]) }
+
+    fn bne(&mut self, target: u16) -> &mut Self {
+        let offset = target as i32 - (self.label() as i32 + 2);
+        assert!(offset >= -128, "branch out of range");
+        self.op(&[0xD0, offset as u8])
+    }
+
+    fn jmp(&mut self, target: u16) -> &mut Self {
+        self.op(&[0x4C, target as u8, (target >> 8) as u8])
+    }
+
+    fn wsync_lines(&mut self, lines: u8) -> &mut Self {
+        self.ldx(lines);
+        let top = self.label();
+        self.sta(tia::WSYNC).dex().bne(top)
+    }
+
+    // a 4K cartridge with the reset vector pointing at the start, the same layout as `System::test_rom`, which is
+    // test-only in the library and so out of reach here
+    fn rom(&self) -> Vec<u8> {
+        let mut rom = vec![0xEA; 4096];
+        rom[..self.code.len()].copy_from_slice(&self.code);
+        rom[0xFFC] = Asm::ORIGIN as u8;
+        rom[0xFFD] = (Asm::ORIGIN >> 8) as u8;
+        rom
+    }
+}
+
+// a 262 line NTSC frame: `setup` runs once, `vblank` once a frame and `line` on each of the 192 visible lines
+fn program(setup: impl Fn(&mut Asm), vblank: impl Fn(&mut Asm), line: impl Fn(&mut Asm)) -> Vec<u8> {
+    let mut asm = Asm::new();
+    asm.op(&[0x78, 0xD8, 0xA2, 0xFF, 0x9A]); // SEI, CLD, LDX #$FF, TXS
+    setup(&mut asm);
+
+    let frame = asm.label();
+    asm.lda(2).sta(tia::VBLANK).sta(tia::VSYNC);
+    asm.sta(tia::WSYNC).sta(tia::WSYNC).sta(tia::WSYNC);
+    asm.lda(0).sta(tia::VSYNC);
+    vblank(&mut asm);
+    asm.wsync_lines(37);
+    asm.lda(0).sta(tia::VBLANK);
+
+    asm.ldy(192);
+    let kernel = asm.label();
+    asm.sta(tia::WSYNC);
+    line(&mut asm);
+    asm.dey().bne(kernel);
+
+    asm.lda(2).sta(tia::VBLANK);
+    asm.wsync_lines(30);
+    asm.jmp(frame);
+    asm.rom()
+}
+
+// runs `frames` frames, applying each scripted event before the frame it's listed for
+fn run(rom: Vec<u8>, frames: u32, script: &[(u32, Port, ControllerEvent)]) -> (Vec<u8>, usize) {
+    let mut system = System::new(rom);
+
+    for frame in 0..frames {
+        for (_, port, event) in script.iter().filter(|(at, _, _)| *at == frame) {
+            system.controller_event(*port, *event);
+        }
+
+        system.run_frame();
+    }
+
+    let height = system.atari().tia.frame_scanlines() as usize;
+    let pixels = system.frame()
+        .chunks(CLOCKS_PER_SCANLINE as usize)
+        .take(height)
+        .flat_map(|row| row[HBLANK..].iter().copied())
+        .collect();
+
+    (pixels, height)
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden")
+}
+
+fn rom_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms")
+}
+
+// one of the homebrew ROMs, assembled from the source next to it
+fn homebrew(name: &str) -> Vec<u8> {
+    let path = rom_dir().join(format!("{}.bin", name));
+    fs::read(&path).unwrap_or_else(|e| panic!("could not read {}: {}", path.display(), e))
+}
+
+fn diff_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target").join("golden-diff")
+}
+
+// goldens are PGMs of raw palette indices, so a comparison is exact whatever the palette
+fn write_pgm(path: &PathBuf, pixels: &[u8], height: usize) {
+    let mut data = format!("P5\n{} {}\n255\n", WIDTH, height).into_bytes();
+    data.extend_from_slice(pixels);
+    fs::write(path, data).unwrap();
+}
+
+fn read_pgm(path: &PathBuf) -> Option<(Vec<u8>, usize)> {
+    let data = fs::read(path).ok()?;
+    let mut fields = Vec::new();
+    let mut pos = 0;
+
+    while fields.len() < 4 {
+        let start = pos;
+
+        while !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+
+        fields.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+        pos += 1;
+    }
+
+    assert_eq!(fields[0], "P5", "{} is not a binary PGM", path.display());
+    assert_eq!(fields[1].parse::<usize>().unwrap(), WIDTH, "{} has the wrong width", path.display());
+    Some((data[pos..].to_vec(), fields[2].parse().unwrap()))
+}
+
+fn write_ppm(path: &PathBuf, rgb: &[(u8, u8, u8)], height: usize) {
+    let mut data = format!("P6\n{} {}\n255\n", WIDTH, height).into_bytes();
+
+    for (r, g, b) in rgb {
+        data.extend_from_slice(&[*r, *g, *b]);
+    }
+
+    fs::write(path, data).unwrap();
+}
+
+fn check_golden(name: &str, (pixels, height): (Vec<u8>, usize)) {
+    let path = golden_dir().join(format!("{}.pgm", name));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(golden_dir()).unwrap();
+        write_pgm(&path, &pixels, height);
+        return;
+    }
+
+    let (golden, golden_height) = read_pgm(&path)
+        .unwrap_or_else(|| panic!("no golden image {}, run with UPDATE_GOLDEN=1 to create it", path.display()));
+
+    if golden == pixels && golden_height == height {
+        return;
+    }
+
+    let palette = |color: u8| TvStandard::Ntsc.palette_rgb(color);
+    let rows = height.max(golden_height);
+    let mut diff = Vec::new();
+    let mut differing = 0;
+
+    // differing pixels are red, the rest of the actual frame is dimmed
+    for index in 0..rows * WIDTH {
+        match (pixels.get(index), golden.get(index)) {
+            (Some(actual), Some(expected)) if actual == expected => {
+                let (r, g, b) = palette(*actual);
+                diff.push((r / 4, g / 4, b / 4));
+            }
+            _ => {
+                differing += 1;
+                diff.push((255, 0, 0));
+            }
+        }
+    }
+
+    fs::create_dir_all(diff_dir()).unwrap();
+    let actual_path = diff_dir().join(format!("{}-actual.ppm", name));
+    let diff_path = diff_dir().join(format!("{}-diff.ppm", name));
+    write_ppm(&actual_path, &pixels.iter().map(|color| palette(*color)).collect::<Vec<_>>(), height);
+    write_ppm(&diff_path, &diff, rows);
+
+    panic!(
+        "{}: {} pixels differ from the golden image ({} lines, expected {}), see {} and {}",
+        name, differing, height, golden_height, actual_path.display(), diff_path.display()
+    );
+}
+
+#[test]
+fn background_per_line() {
+    let rom = program(|_| (), |_| (), |asm| { asm.sty(tia::COLUBK); });
+    check_golden("background_per_line", run(rom, 2, &[]));
+}
+
+#[test]
+fn playfield_reflected() {
+    let rom = program(
+        |asm| {
+            asm.lda(0b1010_0000).sta(tia::PF0);
+            asm.lda(0b1100_1100).sta(tia::PF1);
+            asm.lda(0b0101_0101).sta(tia::PF2);
+            asm.lda(0x01).sta(tia::CTRLPF);
+            asm.lda(0x80).sta(tia::COLUBK);
+        },
+        |_| (),
+        |asm| { asm.sty(tia::COLUPF); }
+    );
+
+    check_golden("playfield_reflected", run(rom, 2, &[]));
+}
+
+#[test]
+fn playfield_score_mode() {
+    let rom = program(
+        |asm| {
+            asm.lda(0b1111_0000).sta(tia::PF0);
+            asm.lda(0b1010_1010).sta(tia::PF1);
+            asm.lda(0b1111_0000).sta(tia::PF2);
+            asm.lda(0x02).sta(tia::CTRLPF);
+            asm.lda(0x46).sta(tia::COLUP0);
+            asm.lda(0xC6).sta(tia::COLUP1);
+        },
+        |_| (),
+        |_| ()
+    );
+
+    check_golden("playfield_score_mode", run(rom, 2, &[]));
+}
+
+#[test]
+fn players_copies_and_reflection() {
+    let rom = program(
+        |asm| {
+            asm.sta(tia::WSYNC).nop(12).sta(tia::RESP0).nop(8).sta(tia::RESP1);
+            asm.lda(0x03).sta(tia::NUSIZ0);
+            asm.lda(0x07).sta(tia::NUSIZ1);
+            asm.lda(0x08).sta(tia::REFP1);
+            asm.lda(0x1E).sta(tia::COLUP0);
+            asm.lda(0x86).sta(tia::COLUP1);
+            asm.lda(0b1110_0101).sta(tia::GRP0);
+            asm.lda(0b1001_1011).sta(tia::GRP1);
+        },
+        |_| (),
+        |_| ()
+    );
+
+    check_golden("players_copies_and_reflection", run(rom, 2, &[]));
+}
+
+#[test]
+fn missiles_and_ball_hmove() {
+    let rom = program(
+        |asm| {
+            asm.sta(tia::WSYNC).nop(10).sta(tia::RESM0).nop(6).sta(tia::RESBL).nop(6).sta(tia::RESM1);
+            asm.lda(0x30).sta(tia::NUSIZ0);
+            asm.lda(0x20).sta(tia::CTRLPF);
+            asm.lda(0x2A).sta(tia::COLUP0);
+            asm.lda(0x9A).sta(tia::COLUP1);
+            asm.lda(0xDA).sta(tia::COLUPF);
+            asm.lda(0x02).sta(tia::ENAM0).sta(tia::ENAM1).sta(tia::ENABL);
+            asm.lda(0xF0).sta(tia::HMM0);
+            asm.lda(0x10).sta(tia::HMM1);
+            asm.lda(0xD0).sta(tia::HMBL);
+        },
+        |asm| { asm.sta(tia::WSYNC).sta(tia::HMOVE); },
+        |_| ()
+    );
+
+    check_golden("missiles_and_ball_hmove", run(rom, 12, &[]));
+}
+
+#[test]
+fn scripted_joystick_and_fire() {
+    let rom = program(
+        |asm| { asm.lda(0xFF).sta(tia::PF1); },
+        |asm| { asm.lda_abs(tia::SWCHA).sta(tia::COLUBK).lda_zp(tia::INPT4).sta(tia::COLUPF); },
+        |_| ()
+    );
+
+    let script = [
+        (1, Port::Left, ControllerEvent::Up(true)),
+        (3, Port::Left, ControllerEvent::Right(true)),
+        (4, Port::Left, ControllerEvent::Up(false)),
+        (4, Port::Right, ControllerEvent::Down(true)),
+        (5, Port::Left, ControllerEvent::Fire(true))
+    ];
+
+    check_golden("scripted_joystick_and_fire", run(rom, 6, &script));
+}
+
+#[test]
+fn rom_timer_playfield() {
+    check_golden("rom_timer_playfield", run(homebrew("timer_playfield"), 20, &[]));
+}
+
+#[test]
+fn rom_sprites_collisions() {
+    // player 0 moves right, fires, backs off a little and is touching player 1 by the last frame
+    let script = [
+        (0, Port::Left, ControllerEvent::Right(true)),
+        (10, Port::Left, ControllerEvent::Right(false)),
+        (40, Port::Left, ControllerEvent::Fire(true)),
+        (45, Port::Left, ControllerEvent::Left(true)),
+        (48, Port::Left, ControllerEvent::Left(false))
+    ];
+
+    check_golden("rom_sprites_collisions", run(homebrew("sprites_collisions"), 60, &script));
+}
+
+#[test]
+fn rom_bankswitch_f8() {
+    check_golden("rom_bankswitch_f8", run(homebrew("bankswitch_f8"), 3, &[]));
+}
@@ -0,0 +1,167 @@
+; 8K cartridge with F8 bank switching. Bank 0 draws the top half of the screen and bank 1 the bottom
+; half, each reading a playfield table that sits at the same address in both banks with different
+; contents. Reading $1FF8 selects bank 0 and $1FF9 bank 1.
+; Assemble with: dasm bankswitch_f8.asm -f3 -obankswitch_f8.bin
+
+        processor 6502
+
+VSYNC   = $00
+VBLANK  = $01
+WSYNC   = $02
+COLUPF  = $08
+COLUBK  = $09
+PF1     = $0E
+PF2     = $0F
+INTIM   = $0284
+TIM64T  = $0296
+BANK0   = $1FF8
+BANK1   = $1FF9
+
+; ---------------------------------------------------------------- bank 0
+
+        org $0000
+        rorg $F000
+
+start   sei
+        cld
+        ldx #$FF
+        txs
+
+main    lda #2
+        sta VBLANK
+        sta VSYNC
+        sta WSYNC
+        sta WSYNC
+        sta WSYNC
+        lda #0
+        sta VSYNC
+
+        lda #43
+        sta TIM64T
+        lda #$3C
+        sta COLUPF
+
+vbwait  lda INTIM
+        bne vbwait
+        sta WSYNC
+        sta VBLANK
+
+        ldy #96
+top     sta WSYNC
+        tya
+        and #$07
+        ora #$40
+        sta COLUBK
+        lda pattern,y
+        sta PF1
+        sta PF2
+        dey
+        bne top
+
+        jmp tobank1
+
+; bank 1 comes back here once it has drawn the bottom half
+overscan
+        sta WSYNC
+        lda #2
+        sta VBLANK
+        lda #0
+        sta PF1
+        sta PF2
+
+        lda #35
+        sta TIM64T
+
+oswait  lda INTIM
+        bne oswait
+        jmp main
+
+        org $0E00
+        rorg $FE00
+
+; a thin line every eighth line
+pattern .byte $FF, $00, $00, $00, $00, $00, $00, $00, $FF, $00, $00, $00, $00, $00, $00, $00
+        .byte $FF, $00, $00, $00, $00, $00, $00, $00, $FF, $00, $00, $00, $00, $00, $00, $00
+        .byte $FF, $00, $00, $00, $00, $00, $00, $00, $FF, $00, $00, $00, $00, $00, $00, $00
+        .byte $FF, $00, $00, $00, $00, $00, $00, $00, $FF, $00, $00, $00, $00, $00, $00, $00
+        .byte $FF, $00, $00, $00, $00, $00, $00, $00, $FF, $00, $00, $00, $00, $00, $00, $00
+        .byte $FF, $00, $00, $00, $00, $00, $00, $00, $FF, $00, $00, $00, $00, $00, $00, $00
+        .byte $FF, $00, $00, $00, $00, $00, $00, $00, $FF, $00, $00, $00, $00, $00, $00, $00
+
+; the instruction after each switch is fetched from the other bank
+        org $0F00
+        rorg $FF00
+
+tobank1 bit BANK1
+        .byte $00, $00, $00
+
+        org $0F10
+        rorg $FF10
+
+        .byte $00, $00, $00
+        jmp overscan
+
+        org $0FE0
+        rorg $FFE0
+
+reset0  bit BANK0
+        jmp start
+
+        org $0FFC
+        rorg $FFFC
+        .word reset0
+        .word reset0
+
+; ---------------------------------------------------------------- bank 1
+
+        org $1000
+        rorg $F000
+
+bottom  ldy #96
+lines   sta WSYNC
+        tya
+        and #$07
+        ora #$C0
+        sta COLUBK
+        lda pattern,y
+        sta PF1
+        sta PF2
+        dey
+        bne lines
+
+        jmp tobank0
+
+        org $1E00
+        rorg $FE00
+
+; checkered where bank 0 has lines
+        .byte $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55
+        .byte $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55
+        .byte $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55
+        .byte $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55
+        .byte $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55
+        .byte $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55
+        .byte $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55, $AA, $55
+
+        org $1F00
+        rorg $FF00
+
+        .byte $00, $00, $00
+        jmp bottom
+
+        org $1F10
+        rorg $FF10
+
+tobank0 bit BANK0
+        .byte $00, $00, $00
+
+        org $1FE0
+        rorg $FFE0
+
+reset1  bit BANK0
+        jmp start
+
+        org $1FFC
+        rorg $FFFC
+        .word reset1
+        .word reset1
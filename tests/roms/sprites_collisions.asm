@@ -0,0 +1,194 @@
+; Two players, a missile and the ball placed with the usual divide-by-15 positioning routine and HMOVE.
+; The joystick in the left port moves player 0 and its fire button recolors it, player 1 walks right
+; on its own, and the background turns red on the frame after the players touch.
+; Assemble with: dasm sprites_collisions.asm -f3 -osprites_collisions.bin
+
+        processor 6502
+
+VSYNC   = $00
+VBLANK  = $01
+WSYNC   = $02
+NUSIZ0  = $04
+COLUP0  = $06
+COLUP1  = $07
+COLUPF  = $08
+COLUBK  = $09
+CTRLPF  = $0A
+RESP0   = $10
+GRP0    = $1B
+GRP1    = $1C
+ENAM0   = $1D
+ENABL   = $1F
+HMP0    = $20
+HMOVE   = $2A
+CXCLR   = $2C
+CXPPMM  = $07
+INPT4   = $0C
+SWCHA   = $0280
+INTIM   = $0284
+TIM64T  = $0296
+
+frame   = $80
+p0x     = $81           ; positions in the order of RESP0-RESBL
+p1x     = $82
+m0x     = $83
+m1x     = $84
+blx     = $85
+bgcolor = $86
+gfx0    = $87
+
+P0Y     = 110
+P1Y     = 104
+
+        org $F000
+
+reset   sei
+        cld
+        ldx #$FF
+        txs
+        lda #0
+        ldx #$7F
+clear   sta $80,x
+        dex
+        bpl clear
+
+        lda #60
+        sta p0x
+        lda #20
+        sta m0x
+        lda #140
+        sta blx
+        lda #$10
+        sta NUSIZ0
+        lda #$30
+        sta CTRLPF
+        lda #$9A
+        sta COLUPF
+        lda #2
+        sta ENAM0
+        sta ENABL
+
+main    lda #2
+        sta VBLANK
+        sta VSYNC
+        sta WSYNC
+        sta WSYNC
+        sta WSYNC
+        lda #0
+        sta VSYNC
+
+        lda #43
+        sta TIM64T
+
+        ; right and left are bits 7 and 6 of SWCHA, low while pressed
+        lda SWCHA
+        asl
+        bcs noright
+        inc p0x
+noright asl
+        bcs noleft
+        dec p0x
+noleft  lda #$1E
+        bit INPT4
+        bmi nofire
+        lda #$C8
+nofire  sta COLUP0
+        lda #$46
+        sta COLUP1
+        lda bgcolor
+        sta COLUBK
+
+        lda frame
+        and #$7F
+        clc
+        adc #10
+        sta p1x
+
+        ldx #4
+place   lda p0x,x
+        jsr posobj
+        dex
+        bpl place
+        sta WSYNC
+        sta HMOVE
+
+vbwait  lda INTIM
+        bne vbwait
+        sta WSYNC
+        sta VBLANK
+
+        ; each line stores the graphics worked out on the line before
+        ldy #192
+        lda #0
+        ldx #0
+kernel  sta WSYNC
+        stx GRP0
+        sta GRP1
+        dey
+        beq done
+        tya
+        sec
+        sbc #P0Y
+        cmp #8
+        bcs p0off
+        tax
+        lda sprite0,x
+        jmp p0set
+p0off   lda #0
+p0set   sta gfx0
+        tya
+        sec
+        sbc #P1Y
+        cmp #8
+        bcs p1off
+        tax
+        lda sprite1,x
+        jmp p1set
+p1off   lda #0
+p1set   ldx gfx0
+        jmp kernel
+
+done    sta WSYNC
+        lda #2
+        sta VBLANK
+        lda #0
+        sta GRP0
+        sta GRP1
+
+        lda #35
+        sta TIM64T
+
+        lda #0
+        bit CXPPMM
+        bpl apart
+        lda #$42
+apart   sta bgcolor
+        sta CXCLR
+        inc frame
+
+oswait  lda INTIM
+        bne oswait
+        jmp main
+
+; A is the position, X the object: 0 and 1 the players, 2 and 3 the missiles, 4 the ball
+posobj  sta WSYNC
+        sec
+divide  sbc #15
+        bcs divide
+        eor #7
+        asl
+        asl
+        asl
+        asl
+        sta HMP0,x
+        sta RESP0,x
+        rts
+
+        org $FE00
+
+sprite0 .byte %00111100, %01111110, %11011011, %11111111, %11111111, %10111101, %11000011, %01111110
+sprite1 .byte %00011000, %00111100, %01111110, %11011011, %11111111, %00100100, %01011010, %10100101
+
+        org $FFFC
+        .word reset
+        .word reset
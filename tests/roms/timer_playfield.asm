@@ -0,0 +1,114 @@
+; Timer-driven NTSC frame with an asymmetric playfield rewritten in the middle of every line.
+; The pattern scrolls and the playfield color changes with a frame counter kept in RAM.
+; Assemble with: dasm timer_playfield.asm -f3 -otimer_playfield.bin
+
+        processor 6502
+
+VSYNC   = $00
+VBLANK  = $01
+WSYNC   = $02
+COLUPF  = $08
+COLUBK  = $09
+CTRLPF  = $0A
+PF0     = $0D
+PF1     = $0E
+PF2     = $0F
+INTIM   = $0284
+TIM64T  = $0296
+
+frame   = $80
+
+        org $F000
+
+reset   sei
+        cld
+        ldx #$FF
+        txs
+        lda #0
+        ldx #$7F
+clear   sta $80,x
+        dex
+        bpl clear
+
+        sta CTRLPF
+        lda #$82
+        sta COLUBK
+
+main    lda #2
+        sta VBLANK
+        sta VSYNC
+        sta WSYNC
+        sta WSYNC
+        sta WSYNC
+        lda #0
+        sta VSYNC
+
+        ; 37 lines of VBLANK timed by the RIOT
+        lda #43
+        sta TIM64T
+
+        lda frame
+        asl
+        asl
+        asl
+        asl
+        ora #$0A
+        sta COLUPF
+
+vbwait  lda INTIM
+        bne vbwait
+        sta WSYNC
+        sta VBLANK
+
+        ; the left half is written before it's drawn, the right half after the left half is done
+        ldy #192
+kernel  sta WSYNC
+        tya
+        clc
+        adc frame
+        and #$0F
+        tax
+        lda left0,x
+        sta PF0
+        lda left1,x
+        sta PF1
+        lda left2,x
+        sta PF2
+        lda right0,x
+        sta PF0
+        lda right1,x
+        sta PF1
+        lda right2,x
+        sta PF2
+        dey
+        bne kernel
+
+        sta WSYNC
+        lda #2
+        sta VBLANK
+        lda #0
+        sta PF0
+        sta PF1
+        sta PF2
+
+        ; 30 lines of overscan
+        lda #35
+        sta TIM64T
+        inc frame
+
+oswait  lda INTIM
+        bne oswait
+        jmp main
+
+        org $FE00
+
+left0   .byte $10, $20, $40, $80, $10, $20, $40, $80, $10, $20, $40, $80, $10, $20, $40, $80
+left1   .byte $80, $40, $20, $10, $08, $04, $02, $01, $80, $40, $20, $10, $08, $04, $02, $01
+left2   .byte $01, $02, $04, $08, $10, $20, $40, $80, $01, $02, $04, $08, $10, $20, $40, $80
+right0  .byte $F0, $F0, $F0, $F0, $F0, $F0, $F0, $F0, $00, $00, $00, $00, $00, $00, $00, $00
+right1  .byte $00, $00, $00, $00, $FF, $FF, $FF, $FF, $00, $00, $00, $00, $FF, $FF, $FF, $FF
+right2  .byte $00, $11, $22, $33, $44, $55, $66, $77, $88, $99, $AA, $BB, $CC, $DD, $EE, $FF
+
+        org $FFFC
+        .word reset
+        .word reset